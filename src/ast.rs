@@ -1,6 +1,11 @@
+use std::{collections::HashSet, fmt};
+
 use tracing::{event, span};
 
-use crate::token::{self, Token};
+use crate::{
+    source::{FileId, Span},
+    token::{self, Token},
+};
 
 pub type AstIdx = usize;
 pub type TokenIdx = usize;
@@ -9,6 +14,18 @@ pub type TokenIdx = usize;
 pub enum Kind {
     Number {
         value: token::Symbol,
+        literal: token::Literal,
+    },
+    Bool {
+        value: bool,
+    },
+    Text {
+        value: token::Symbol,
+    },
+    /// Text with interpolated expressions, the parts alternate between `Text` nodes and the
+    /// expressions. Later phases turn it into a chain of `to_text` calls and concatenations.
+    Interpolation {
+        parts: Vec<AstIdx>,
     },
     Identifier {
         name: token::Symbol,
@@ -26,62 +43,388 @@ pub enum Kind {
     Loop {
         body: AstIdx,
     },
+    /// `for binding in iter { body }`
+    For {
+        binding: AstIdx,
+        iter: AstIdx,
+        body: AstIdx,
+    },
+    /// `break` or `break value` to end a loop with a value
+    Break {
+        value: Option<AstIdx>,
+    },
+    /// Inside of a handler `continue value` resumes the computation that raised the effect
+    Continue {
+        value: Option<AstIdx>,
+    },
+    /// `match scrutinee { arms }`
+    Match {
+        scrutinee: AstIdx,
+        arms: Vec<AstIdx>,
+    },
+    /// `pattern and guard -> body`
+    MatchArm {
+        pattern: AstIdx,
+        guard: Option<AstIdx>,
+        body: AstIdx,
+    },
+    /// `effect (Int) -> Int`, the signature is the function type of raising it
+    Effect {
+        signature: AstIdx,
+    },
+    /// `raise DoNothing(n)`, its value is the one the handler continues with
+    Raise {
+        effect: AstIdx,
+    },
+    /// `expr with { handlers }`, handles the effects raised while evaluating the expression
+    Handle {
+        expr: AstIdx,
+        handlers: Vec<AstIdx>,
+    },
+    /// `pattern, continuation -> body`, the continuation can be called to resume the computation,
+    /// without it `continue value` does the same
+    Handler {
+        pattern: AstIdx,
+        continuation: Option<token::Symbol>,
+        body: AstIdx,
+    },
     UnaryOp {
         expr: AstIdx,
         op: token::Kind,
     },
+    /// An operator used as a value or as the name of a definition, `(+)`
+    Operator {
+        op: token::Kind,
+    },
     Call {
         callee: AstIdx,
         args: Vec<AstIdx>,
     },
+    /// `object.member`, it can be a field access or a call with UFCS, name resolution tells them apart.
+    /// The member is an identifier or a path like in `69_420.std::print`
+    Member {
+        object: AstIdx,
+        member: AstIdx,
+    },
+    /// `receiver.method(args)`, always a call, with the receiver as the first argument
+    MethodCall {
+        receiver: AstIdx,
+        method: AstIdx,
+        args: Vec<AstIdx>,
+    },
+    /// `<generics>(params) -> result, effects { body }`, without a body it is a function type
     Function {
+        generics: Vec<AstIdx>,
         params: Vec<AstIdx>,
-        result: AstIdx,
-        body: AstIdx,
+        result: Option<AstIdx>,
+        effects: Vec<AstIdx>,
+        body: Option<AstIdx>,
+    },
+    /// `name : Type = default`, unnamed parameters only have a type. A lone identifier like in
+    /// `(x) -> {}` is stored as the type too, it is up to name resolution to tell what it is.
+    /// A variadic parameter, `args...: Args...`, takes the rest of the arguments.
+    Param {
+        name: Option<token::Symbol>,
+        ty: Option<AstIdx>,
+        default: Option<AstIdx>,
+        variadic: bool,
+    },
+    /// `A : Bound` inside of `<...>`, or `Args...` for a pack of any number of types
+    GenericParam {
+        name: token::Symbol,
+        bound: Option<AstIdx>,
+        variadic: bool,
+    },
+    /// `<A, B>[Ok := A or Error := B]`, a type with generic parameters. Generic functions keep
+    /// them in [`Kind::Function`] instead.
+    GenericType {
+        generics: Vec<AstIdx>,
+        ty: AstIdx,
+    },
+    /// `Vec2<A>` or `first.parse<Nat>`, the generic arguments given explicitly
+    TypeApplication {
+        ty: AstIdx,
+        args: Vec<AstIdx>,
+    },
+    /// `args...`, expands a variadic pack, the values in a call or the types in a function type
+    Spread {
+        expr: AstIdx,
+    },
+    /// `ref<Lifetime> V`, a reference with an explicit lifetime. Without it `ref V` is a prefix operator.
+    Reference {
+        lifetime: AstIdx,
+        ty: AstIdx,
     },
     Block {
         statements: Vec<AstIdx>,
     },
-    Import,
-    Return {
+    /// An expression in parentheses
+    Group {
         expr: AstIdx,
     },
+    /// `target : ty = value`, `target := value` or just `target : ty`. The target is a name, an operator
+    /// or a pattern to destructure the value, then `else { ... }` runs when the value does not match it.
+    Declaration {
+        target: AstIdx,
+        ty: Option<AstIdx>,
+        value: Option<AstIdx>,
+        otherwise: Option<AstIdx>,
+        public: bool,
+    },
+    /// `[a, b]`, `[x : Real, y : Real]` or `[]`, used for tuples, records, arrays and their types
+    Tuple {
+        elements: Vec<AstIdx>,
+    },
+    /// A named element of a tuple: `name : ty`, `name : ty = value`, `name := value` or `name = value`
+    Field {
+        name: AstIdx,
+        ty: Option<AstIdx>,
+        value: Option<AstIdx>,
+    },
+    /// `[Int or Real]`, the alternatives can be fields to name them, `[Ok := A or Error := B]`
+    Variant {
+        alternatives: Vec<AstIdx>,
+    },
+    /// `[Int * 3]` or `[3 * 3 * Int]` in a type, the sizes are number literals
+    ArrayType {
+        element: AstIdx,
+        sizes: Vec<AstIdx>,
+    },
+    /// `Type::[args]`
+    Construct {
+        ty: AstIdx,
+        args: AstIdx,
+    },
+    /// `value is pattern`, checks the value against the pattern and binds its names if it matches
+    Is {
+        value: AstIdx,
+        pattern: AstIdx,
+    },
+    Pattern(Pattern),
+    /// The value of a declaration that is provided by the compiler, `print : (value: Text) -> [] = external`
+    External,
+    /// `import src::std` or `import src::depA::[nested::dep_b, dep_a]`, the selection has the imports
+    /// relative to the path. Assigning it to a name, `deps := import src::std`, makes a namespace.
+    Import {
+        path: Vec<token::Symbol>,
+        selection: Option<Vec<AstIdx>>,
+    },
+    Return {
+        expr: Option<AstIdx>,
+    },
     Error,
 }
 
+/// What goes in `match` arms, after `is` and on the left of destructuring declarations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Pattern {
+    /// `_`, matches anything without binding it
+    Wildcard,
+    /// A name that gets bound to the value. It can also be a variant without a payload, like `Red`,
+    /// or a type, like in `x is Int`, name resolution tells them apart.
+    Binding {
+        name: token::Symbol,
+    },
+    /// A number, text or boolean, the value is the literal node
+    Literal {
+        value: AstIdx,
+    },
+    /// `[a, b]`, matches tuples and records
+    Tuple {
+        elements: Vec<AstIdx>,
+    },
+    /// `pattern=field`, matches a field of a record by its name, like `[x, y]=first` or `n=name`
+    Field {
+        pattern: AstIdx,
+        field: token::Symbol,
+    },
+    /// `Cat::[name]` or `Bool::True`, the path to the variant and the pattern for its payload
+    Variant {
+        path: AstIdx,
+        payload: Option<AstIdx>,
+    },
+    /// `Cat::[name] or Dog::[name]`, every alternative has to bind the same names
+    Or {
+        alternatives: Vec<AstIdx>,
+    },
+    /// `n : Int`
+    Typed {
+        pattern: AstIdx,
+        ty: AstIdx,
+    },
+}
+
 pub struct Node {
     pub kind: Kind,
     pub span: (TokenIdx, TokenIdx),
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Something else had to be at this point, it says what
+    Expected(&'static str),
+    /// Parameters in parentheses that are not followed by `->`
+    MissingResult,
+    /// `pub` in front of something that is not a declaration
+    PubWithoutDeclaration,
+    /// An array type with more than one element type, like `[Int * Real]`
+    ArrayElementTypes,
+    /// An array type with a size that is not an integer, like `[Int * 2.5]`
+    ArraySize,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::Expected(expected) => write!(f, "expected {}", expected),
+            ErrorKind::MissingResult => write!(f, "parameters without a result, expected `->` after them"),
+            ErrorKind::PubWithoutDeclaration => write!(f, "only declarations can be public"),
+            ErrorKind::ArrayElementTypes => write!(f, "array types have a single element type, like `[Int * 3]`"),
+            ErrorKind::ArraySize => write!(f, "array sizes must be integer literals"),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub span: (TokenIdx, TokenIdx),
+}
+
+/// A path imported by a module, for the module loader
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedPath {
+    pub path: Vec<token::Symbol>,
+    /// The name the import is assigned to, without it the names go into the module itself
+    pub namespace: Option<token::Symbol>,
+    /// The import node that has the last part of the path
+    pub node: AstIdx,
+}
+
 pub struct Module {
+    pub file: FileId,
     pub definitions: Vec<AstIdx>,
+    pub errors: Vec<Error>,
     ast: Vec<Node>,
+    tokens: Vec<Token>,
 }
 
 impl Module {
     pub fn get(&self, idx: AstIdx) -> &Node {
         &self.ast[idx]
     }
+
+    /// Source span covered by a node, from the start of its first token to the end of its last one
+    pub fn span(&self, idx: AstIdx) -> Span {
+        self.token_span(self.get(idx).span)
+    }
+
+    /// Source span covered by a range of tokens, an empty range points at the token after it
+    pub fn token_span(&self, (first, last): (TokenIdx, TokenIdx)) -> Span {
+        let token_span = |idx: TokenIdx| match self.tokens.get(idx).or(self.tokens.last()) {
+            Some(token) => token.span,
+            None => Span::new(self.file, 0, 0),
+        };
+        token_span(first).merge(token_span(last.max(first + 1) - 1))
+    }
+
+    /// Every path imported by the definitions of the module with the selections flattened, so
+    /// `deps := import src::depA::[nested::dep_b, dep_a]` gives `src::depA::nested::dep_b` and
+    /// `src::depA::dep_a`, both in the `deps` namespace
+    #[allow(dead_code)] // For the module loader
+    pub fn imports(&self) -> Vec<ImportedPath> {
+        let mut imports = Vec::new();
+        for &definition in &self.definitions {
+            let (import, namespace) = match self.get(definition).kind {
+                Kind::Import { .. } => (definition, None),
+                Kind::Declaration {
+                    target,
+                    value: Some(value),
+                    ..
+                } if matches!(self.get(value).kind, Kind::Import { .. }) => match self.get(target).kind {
+                    Kind::Identifier { name } => (value, Some(name)),
+                    _ => (value, None),
+                },
+                _ => continue,
+            };
+            self.flatten_import(import, Vec::new(), namespace, &mut imports);
+        }
+        imports
+    }
+
+    fn flatten_import(
+        &self,
+        import: AstIdx,
+        mut prefix: Vec<token::Symbol>,
+        namespace: Option<token::Symbol>,
+        imports: &mut Vec<ImportedPath>,
+    ) {
+        let Kind::Import { path, selection } = &self.get(import).kind else {
+            return;
+        };
+        prefix.extend(path);
+        match selection {
+            None => imports.push(ImportedPath {
+                path: prefix,
+                namespace,
+                node: import,
+            }),
+            Some(selection) => {
+                for &nested in selection {
+                    self.flatten_import(nested, prefix.clone(), namespace, imports);
+                }
+            }
+        }
+    }
 }
 
-struct Parser {
+struct Parser<I: Iterator<Item = Token>> {
+    file: FileId,
+    /// Tokens pulled from the source so far, nodes point into it
     tokens: Vec<token::Token>,
+    source: I,
     pos: TokenIdx,
     nodes: Vec<Node>,
+    errors: Vec<Error>,
+    /// How many parentheses and brackets we are inside of, newlines do not end statements inside them
+    nesting: usize,
+    /// Whether we are parsing a type, `[Int * 3]` is an array type there
+    in_type: bool,
+    /// Where `<` was tried as generic arguments and turned out not to be, with the state that can change the
+    /// outcome. Without it nested attempts would be retried after every backtrack, doubling the work each level.
+    not_type_arguments: HashSet<(TokenIdx, bool, bool, bool)>,
 }
 
-impl Parser {
-    fn new(tokens: Vec<token::Token>) -> Self {
+impl<I: Iterator<Item = Token>> Parser<I> {
+    fn new(file: FileId, source: I) -> Self {
         Parser {
-            tokens,
+            file,
+            tokens: Vec::new(),
+            source,
             pos: 0,
             nodes: Vec::new(),
+            errors: Vec::new(),
+            nesting: 0,
+            in_type: false,
+            not_type_arguments: HashSet::new(),
         }
     }
 
-    fn peak(&self) -> Option<&token::Token> {
-        self.tokens.get(self.pos)
+    /// Tokens are only pulled from the source once we look at them
+    fn peak(&mut self) -> Option<token::Token> {
+        self.peak_nth(0)
+    }
+
+    fn peak_nth(&mut self, n: usize) -> Option<token::Token> {
+        while self.tokens.len() <= self.pos + n {
+            let Some(token) = self.source.next() else { break };
+            self.tokens.push(token);
+        }
+        self.tokens.get(self.pos + n).copied()
+    }
+
+    fn peak_kind(&mut self, kind: token::Kind) -> bool {
+        self.peak().is_some_and(|t| t.kind == kind)
     }
 
     fn save_ast(&mut self, ast: Node) -> AstIdx {
@@ -89,8 +432,78 @@ impl Parser {
         self.nodes.push(ast);
         self.nodes.len() - 1
     }
+    /// Consumes the next token if it is of the given kind
+    fn eat(&mut self, kind: token::Kind) -> bool {
+        let matches = self.peak().is_some_and(|t| t.kind == kind);
+        if matches {
+            self.consume();
+        }
+        matches
+    }
+
+    /// Consumes the next token if it is of the given kind, reports an error otherwise
+    fn expect(&mut self, kind: token::Kind, expected: &'static str) {
+        if !self.eat(kind) {
+            self.error(ErrorKind::Expected(expected), (self.pos, self.pos));
+        }
+    }
+
+    /// Skips tokens until the given closing delimiter, or the end of the file, to recover from an error.
+    /// Anything in between parentheses, brackets or braces is skipped as a whole.
+    fn skip_until(&mut self, close: token::Kind) {
+        let mut depth = 0usize;
+        while let Some(token) = self.peak() {
+            match token.kind {
+                kind if kind == close && depth == 0 => break,
+                token::Kind::LParen | token::Kind::LBracket | token::Kind::LBrace => depth += 1,
+                token::Kind::RParen | token::Kind::RBracket | token::Kind::RBrace if depth == 0 => break,
+                token::Kind::RParen | token::Kind::RBracket | token::Kind::RBrace => depth -= 1,
+                _ => {}
+            }
+            self.consume();
+        }
+    }
+
+    /// Skips the rest of the line to recover from an error in a list separated by line breaks,
+    /// like the arms of a match. It stops at a comma too, and at the `}` that closes the list.
+    fn skip_line(&mut self) {
+        let mut depth = 0usize;
+        while let Some(token) = self.peak() {
+            match token.kind {
+                _ if depth == 0 && token.newline_before => break,
+                token::Kind::Comma | token::Kind::RBrace if depth == 0 => break,
+                token::Kind::LParen | token::Kind::LBracket | token::Kind::LBrace => depth += 1,
+                token::Kind::RParen | token::Kind::RBracket | token::Kind::RBrace => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+            self.consume();
+        }
+    }
+
+    fn error(&mut self, kind: ErrorKind, span: (TokenIdx, TokenIdx)) {
+        let error = Error { kind, span };
+        event!(tracing::Level::DEBUG, "Parser error: {:?}", error);
+        self.errors.push(error);
+    }
+
+    /// Reports an error and creates an error node for the tokens from `start`
+    fn error_node(&mut self, kind: ErrorKind, start: TokenIdx) -> AstIdx {
+        self.error(kind, (start, self.pos));
+        self.save_ast(Node {
+            kind: Kind::Error,
+            span: (start, self.pos),
+        })
+    }
+
+    /// Whether the next token can continue the current expression. A line break ends the
+    /// statement, unless we are inside of parentheses or brackets.
+    fn continues_expression(&mut self) -> bool {
+        let nested = self.nesting > 0;
+        self.peak().is_some_and(|t| !t.newline_before || nested)
+    }
+
     fn consume(&mut self) -> token::Token {
-        let token = self.tokens[self.pos];
+        let token = self.peak().expect("Consumed past the last token");
         event!(tracing::Level::DEBUG, "Consuming token {:?}", token.kind);
         self.pos += 1;
         token
@@ -101,22 +514,93 @@ impl Parser {
     }
 
     fn parse_atom(&mut self) -> AstIdx {
+        let start = self.pos;
+        // Closing delimiters and commas are left for whoever is parsing the list they belong to
+        let closes = |t: Token| {
+            matches!(t.kind, token::Kind::RParen | token::Kind::RBracket | token::Kind::RBrace | token::Kind::Comma)
+        };
+        if self.peak().is_none_or(closes) {
+            return self.error_node(ErrorKind::Expected("an expression"), start);
+        }
         let token = self.consume();
         let span = span!(tracing::Level::DEBUG, "parse_atom", token = ?token.kind);
         let _enter = span.enter();
+        if let Some(precedence) = prefix_precedence(token.kind) {
+            if token.kind == token::Kind::Ref
+                && self.type_arguments_follow()
+                && let Some(lifetimes) = self.parse_type_arguments(true)
+            {
+                if lifetimes.len() != 1 {
+                    self.error(ErrorKind::Expected("a single lifetime"), (start, self.pos));
+                }
+                let ty = self.parse_expresion(precedence);
+                return self.save_ast(Node {
+                    kind: Kind::Reference {
+                        lifetime: lifetimes[0],
+                        ty,
+                    },
+                    span: (start, self.pos),
+                });
+            }
+            let expr = self.parse_expresion(precedence);
+            return self.save_ast(Node {
+                kind: Kind::UnaryOp { expr, op: token.kind },
+                span: (start, self.pos),
+            });
+        }
         match token.kind {
-            token::Kind::Number(symbol) => self.save_ast(Node {
-                kind: Kind::Number { value: symbol },
-                span: (self.pos, self.pos + 1),
+            token::Kind::Number(symbol, literal) => self.save_ast(Node {
+                kind: Kind::Number { value: symbol, literal },
+                span: (start, self.pos),
             }),
+            token::Kind::True | token::Kind::False => self.save_ast(Node {
+                kind: Kind::Bool { value: token.kind == token::Kind::True },
+                span: (start, self.pos),
+            }),
+            token::Kind::Text(symbol) => self.save_ast(Node {
+                kind: Kind::Text { value: symbol },
+                span: (start, self.pos),
+            }),
+            token::Kind::TextStart(symbol) => {
+                let mut parts = vec![self.save_ast(Node {
+                    kind: Kind::Text { value: symbol },
+                    span: (start, self.pos),
+                })];
+                loop {
+                    parts.push(self.parse_expresion(0));
+                    let part_start = self.pos;
+                    let (symbol, last) = match self.peak().map(|t| t.kind) {
+                        Some(token::Kind::TextMiddle(symbol)) => (symbol, false),
+                        Some(token::Kind::TextEnd(symbol)) => (symbol, true),
+                        _ => break, // The lexer already reported the unterminated text
+                    };
+                    self.consume();
+                    parts.push(self.save_ast(Node {
+                        kind: Kind::Text { value: symbol },
+                        span: (part_start, self.pos),
+                    }));
+                    if last {
+                        break;
+                    }
+                }
+                self.save_ast(Node {
+                    kind: Kind::Interpolation { parts },
+                    span: (start, self.pos),
+                })
+            }
             token::Kind::Identifier(symbol) => self.save_ast(Node {
                 kind: Kind::Identifier { name: symbol },
-                span: (self.pos, self.pos + 1),
+                span: (start, self.pos),
+            }),
+            // Patterns are parsed as expressions first, so the wildcard can show up in any of them
+            token::Kind::Underscore => self.save_ast(Node {
+                kind: Kind::Pattern(Pattern::Wildcard),
+                span: (start, self.pos),
             }),
             token::Kind::If => {
                 let cond = self.parse_expresion(0);
                 let if_branch = self.parse_expresion(0);
-                let else_branch = if self.peak().map_or(false, |t| t.kind == token::Kind::Else) {
+                let else_branch = if self.peak().is_some_and(|t| t.kind == token::Kind::Else) {
                     self.consume(); // consume 'else'
                     Some(self.parse_expresion(0))
                 } else {
@@ -128,35 +612,418 @@ impl Parser {
                         if_branch,
                         else_branch,
                     },
-                    span: (self.pos, self.pos + 1),
+                    span: (start, self.pos),
                 })
             }
+            token::Kind::LParen => {
+                if let (Some(op), Some(token::Kind::RParen)) = (self.peak().map(|t| t.kind), self.peak_nth(1).map(|t| t.kind))
+                    && (infix_precedence(op).is_some() || prefix_precedence(op).is_some())
+                {
+                    self.consume(); // operator
+                    self.consume(); // closing parenthesis
+                    return self.save_ast(Node {
+                        kind: Kind::Operator { op },
+                        span: (start, self.pos),
+                    });
+                }
+                let (elements, trailing_comma) = self.parse_parameters();
+                if self.continues_expression() && self.peak_kind(token::Kind::Arrow) {
+                    return self.parse_function(start, Vec::new(), elements);
+                }
+                match elements.as_slice() {
+                    &[expr] if !trailing_comma && !matches!(self.get(expr).kind, Kind::Param { .. }) => self.save_ast(Node {
+                        kind: Kind::Group { expr },
+                        span: (start, self.pos),
+                    }),
+                    _ => self.error_node(ErrorKind::MissingResult, start),
+                }
+            }
+            token::Kind::LessThan => {
+                let generics = self.parse_generics();
+                let body_start = self.pos;
+                if self.eat(token::Kind::LParen) {
+                    let (params, _) = self.parse_parameters();
+                    if self.continues_expression() && self.peak_kind(token::Kind::Arrow) {
+                        self.parse_function(start, generics, params)
+                    } else {
+                        self.error_node(ErrorKind::MissingResult, start)
+                    }
+                } else if self.eat(token::Kind::LBracket) {
+                    let in_type = std::mem::replace(&mut self.in_type, true);
+                    let ty = self.parse_brackets(body_start);
+                    self.in_type = in_type;
+                    self.save_ast(Node {
+                        kind: Kind::GenericType { generics, ty },
+                        span: (start, self.pos),
+                    })
+                } else {
+                    self.error_node(ErrorKind::Expected("`(` or `[` after the generic parameters"), start)
+                }
+            }
             token::Kind::LBrace => {
+                // Statements inside a block are separated by newlines again
+                let nesting = std::mem::take(&mut self.nesting);
+                let in_type = std::mem::take(&mut self.in_type);
                 let mut statements = Vec::new();
-                while self.peak().map_or(false, |t| t.kind != token::Kind::RBrace) {
-                    statements.push(self.parse_expresion(0));
+                while self.peak().is_some_and(|t| t.kind != token::Kind::RBrace) {
+                    statements.push(self.parse_statement());
                 }
-                self.consume(); // consume 'RBrace'
+                self.nesting = nesting;
+                self.in_type = in_type;
+                self.expect(token::Kind::RBrace, "`}`");
                 self.save_ast(Node {
                     kind: Kind::Block { statements },
-                    span: (self.pos, self.pos + 1),
+                    span: (start, self.pos),
                 })
             }
+            token::Kind::LBracket => self.parse_brackets(start),
+            token::Kind::External => self.save_ast(Node {
+                kind: Kind::External,
+                span: (start, self.pos),
+            }),
+            token::Kind::Import => self.parse_import(start),
+            token::Kind::Effect => {
+                let signature = self.parse_type(ASSIGNMENT_PRECEDENCE + 1);
+                self.save_ast(Node {
+                    kind: Kind::Effect { signature },
+                    span: (start, self.pos),
+                })
+            }
+            token::Kind::Raise => {
+                let effect = self.parse_expresion(ASSIGNMENT_PRECEDENCE + 1);
+                self.save_ast(Node {
+                    kind: Kind::Raise { effect },
+                    span: (start, self.pos),
+                })
+            }
+            token::Kind::Pub => {
+                let declaration = self.parse_expresion(0);
+                let node = &mut self.nodes[declaration];
+                match &mut node.kind {
+                    Kind::Declaration { public, .. } => {
+                        *public = true;
+                        node.span.0 = start;
+                        declaration
+                    }
+                    _ => self.error_node(ErrorKind::PubWithoutDeclaration, start),
+                }
+            }
             token::Kind::Return => {
-                let expr = self.parse_expresion(0);
+                let expr = self.parse_optional_value();
                 self.save_ast(Node {
                     kind: Kind::Return { expr },
-                    span: (self.pos, self.pos + 1),
+                    span: (start, self.pos),
                 })
             }
-            _ => {
-                // Handle unexpected token
+            token::Kind::Break => {
+                let value = self.parse_optional_value();
+                self.save_ast(Node {
+                    kind: Kind::Break { value },
+                    span: (start, self.pos),
+                })
+            }
+            token::Kind::Continue => {
+                let value = self.parse_optional_value();
+                self.save_ast(Node {
+                    kind: Kind::Continue { value },
+                    span: (start, self.pos),
+                })
+            }
+            token::Kind::Loop => {
+                let body = self.parse_block();
+                self.save_ast(Node {
+                    kind: Kind::Loop { body },
+                    span: (start, self.pos),
+                })
+            }
+            token::Kind::For => {
+                let binding = self.parse_expresion(ASSIGNMENT_PRECEDENCE + 1);
+                let binding = self.pattern_from(binding);
+                self.expect(token::Kind::In, "`in`");
+                let iter = self.parse_expresion(0);
+                let body = self.parse_block();
                 self.save_ast(Node {
-                    kind: Kind::Error,
-                    span: (self.pos, self.pos + 1),
+                    kind: Kind::For { binding, iter, body },
+                    span: (start, self.pos),
                 })
             }
+            token::Kind::Match => {
+                let scrutinee = self.parse_expresion(0);
+                if !self.eat(token::Kind::LBrace) {
+                    return self.error_node(ErrorKind::Expected("`{` with the arms of the match"), start);
+                }
+                let arms = self.parse_arms(Self::parse_match_arm);
+                self.save_ast(Node {
+                    kind: Kind::Match { scrutinee, arms },
+                    span: (start, self.pos),
+                })
+            }
+            _ => self.error_node(ErrorKind::Expected("an expression"), start),
+        }
+    }
+
+    /// The arms of a match or the handlers of a `with`, after the opening brace. They are separated
+    /// by line breaks or commas, and a malformed arm only loses the rest of its line.
+    fn parse_arms(&mut self, parse_arm: fn(&mut Self) -> AstIdx) -> Vec<AstIdx> {
+        // Like statements, the arms are separated by newlines
+        let nesting = std::mem::take(&mut self.nesting);
+        let in_type = std::mem::take(&mut self.in_type);
+        let mut arms = Vec::new();
+        while self.peak().is_some_and(|t| t.kind != token::Kind::RBrace) {
+            let arm_start = self.pos;
+            arms.push(parse_arm(self));
+            if self.pos == arm_start {
+                // A stray closer at the start of a line, the error is already reported
+                self.consume();
+                continue;
+            }
+            if self.eat(token::Kind::Comma) {
+                continue;
+            }
+            match self.peak() {
+                Some(token) if token.kind == token::Kind::RBrace || token.newline_before => {}
+                Some(_) => {
+                    self.error(ErrorKind::Expected("`,`, a line break or `}`"), (self.pos, self.pos));
+                    self.skip_line();
+                }
+                None => break,
+            }
+        }
+        self.nesting = nesting;
+        self.in_type = in_type;
+        self.expect(token::Kind::RBrace, "`}`");
+        arms
+    }
+
+    /// An arm of a match, `Cat::[name] or Dog::[name] and name <> "" -> name.print`
+    fn parse_match_arm(&mut self) -> AstIdx {
+        let start = self.pos;
+        let pattern = self.parse_pattern();
+        let guard = (self.continues_expression() && self.eat(token::Kind::And))
+            .then(|| self.parse_expresion(ARROW_PRECEDENCE + 1));
+        let body = if self.continues_expression() && self.eat(token::Kind::Arrow) {
+            self.parse_expresion(0)
+        } else {
+            // Only this arm is lost, the next one starts in the next line
+            let body_start = self.pos;
+            self.skip_line();
+            self.error_node(ErrorKind::Expected("`->` and the body of the arm"), body_start)
+        };
+        self.save_ast(Node {
+            kind: Kind::MatchArm { pattern, guard, body },
+            span: (start, self.pos),
+        })
+    }
+
+    /// A handler after `with`, `Spawn::[task], continue_spawner -> body`
+    fn parse_handler(&mut self) -> AstIdx {
+        let start = self.pos;
+        let pattern = self.parse_pattern();
+        let continuation = if self.continues_expression() && self.eat(token::Kind::Comma) {
+            match self.peak().map(|t| t.kind) {
+                Some(token::Kind::Identifier(name)) => {
+                    self.consume();
+                    Some(name)
+                }
+                _ => {
+                    self.error(ErrorKind::Expected("a name for the continuation"), (self.pos, self.pos));
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let body = if self.continues_expression() && self.eat(token::Kind::Arrow) {
+            self.parse_expresion(0)
+        } else {
+            let body_start = self.pos;
+            self.skip_line();
+            self.error_node(ErrorKind::Expected("`->` and the body of the handler"), body_start)
+        };
+        self.save_ast(Node {
+            kind: Kind::Handler {
+                pattern,
+                continuation,
+                body,
+            },
+            span: (start, self.pos),
+        })
+    }
+
+    /// A pattern like `Cat::[name] or Dog::[name]`, the `and` after it is not part of it so it can start
+    /// a guard. Like the targets of declarations, that are only known to be patterns once we find the
+    /// `:=`, each alternative is parsed as an expression and then turned into a pattern.
+    fn parse_pattern(&mut self) -> AstIdx {
+        let start = self.pos;
+        let mut alternatives = Vec::new();
+        loop {
+            let alternative_start = self.pos;
+            let expr = self.parse_expresion(AND_PRECEDENCE + 1);
+            let mut pattern = self.pattern_from(expr);
+            if self.continues_expression() && self.eat(token::Kind::Colon) {
+                let ty = self.parse_type(AND_PRECEDENCE + 1);
+                pattern = self.save_ast(Node {
+                    kind: Kind::Pattern(Pattern::Typed { pattern, ty }),
+                    span: (alternative_start, self.pos),
+                });
+            }
+            alternatives.push(pattern);
+            if !(self.continues_expression() && self.eat(token::Kind::Or)) {
+                break;
+            }
+        }
+        if alternatives.len() == 1 {
+            return alternatives[0];
+        }
+        self.save_ast(Node {
+            kind: Kind::Pattern(Pattern::Or { alternatives }),
+            span: (start, self.pos),
+        })
+    }
+
+    /// Turns an expression into the pattern it spells, reporting the parts that can not be one
+    fn pattern_from(&mut self, expr: AstIdx) -> AstIdx {
+        let span = self.get(expr).span;
+        let pattern = match self.get(expr).kind.clone() {
+            // Errors are already reported
+            Kind::Pattern(_) | Kind::Error => return expr,
+            Kind::Identifier { name } => Pattern::Binding { name },
+            Kind::Number { .. } | Kind::Text { .. } | Kind::Bool { .. } => Pattern::Literal { value: expr },
+            Kind::UnaryOp {
+                expr: number,
+                op: token::Kind::Minus,
+            } if matches!(self.get(number).kind, Kind::Number { .. }) => Pattern::Literal { value: expr },
+            Kind::Group { expr } => return self.pattern_from(expr),
+            Kind::Tuple { elements } => Pattern::Tuple {
+                elements: elements.into_iter().map(|element| self.pattern_from(element)).collect(),
+            },
+            Kind::Variant { alternatives } => Pattern::Or {
+                alternatives: alternatives.into_iter().map(|alternative| self.pattern_from(alternative)).collect(),
+            },
+            Kind::BinaryOp {
+                lhs,
+                rhs,
+                op: token::Kind::Or,
+            } => Pattern::Or {
+                alternatives: vec![self.pattern_from(lhs), self.pattern_from(rhs)],
+            },
+            Kind::Field {
+                name,
+                ty: None,
+                value: Some(field),
+            } if let Kind::Identifier { name: field } = self.get(field).kind => Pattern::Field {
+                pattern: self.pattern_from(name),
+                field,
+            },
+            Kind::Field {
+                name,
+                ty: Some(ty),
+                value: None,
+            } => Pattern::Typed {
+                pattern: self.pattern_from(name),
+                ty,
+            },
+            Kind::BinaryOp {
+                op: token::Kind::ColonColon,
+                ..
+            } => Pattern::Variant {
+                path: expr,
+                payload: None,
+            },
+            Kind::Construct { ty, args } => Pattern::Variant {
+                path: ty,
+                payload: Some(self.pattern_from(args)),
+            },
+            _ => {
+                self.error(ErrorKind::Expected("a pattern"), span);
+                return self.save_ast(Node { kind: Kind::Error, span });
+            }
+        };
+        self.save_ast(Node {
+            kind: Kind::Pattern(pattern),
+            span,
+        })
+    }
+
+    /// The path of an import, like `src::std`, after the `import` or inside of a selection. A selection
+    /// at the end only imports some names from the path, `src::depA::[nested::dep_b, dep_a]`.
+    fn parse_import(&mut self, start: TokenIdx) -> AstIdx {
+        let mut path = Vec::new();
+        let mut selection = None;
+        loop {
+            let next = self.continues_expression().then(|| self.peak().map(|t| t.kind)).flatten();
+            match next {
+                Some(token::Kind::Identifier(name)) => {
+                    self.consume();
+                    path.push(name);
+                }
+                Some(token::Kind::LBracket) if !path.is_empty() => {
+                    self.consume();
+                    selection = Some(self.parse_import_selection());
+                    break;
+                }
+                _ => {
+                    self.error(ErrorKind::Expected("a module name"), (self.pos, self.pos));
+                    break;
+                }
+            }
+            if !(self.continues_expression() && self.eat(token::Kind::ColonColon)) {
+                break;
+            }
         }
+        self.save_ast(Node {
+            kind: Kind::Import { path, selection },
+            span: (start, self.pos),
+        })
+    }
+
+    /// The imports between the brackets of a selection, separated by commas or line breaks
+    fn parse_import_selection(&mut self) -> Vec<AstIdx> {
+        self.nesting += 1;
+        let mut selection = Vec::new();
+        while self.peak().is_some_and(|t| t.kind != token::Kind::RBracket) {
+            let start = self.pos;
+            let import = self.parse_import(start);
+            if self.pos == start {
+                // Not even a name, the error is already reported
+                self.skip_until(token::Kind::RBracket);
+                break;
+            }
+            selection.push(import);
+            if self.eat(token::Kind::Comma) {
+                continue;
+            }
+            match self.peak() {
+                Some(token) if token.kind == token::Kind::RBracket || token.newline_before => {}
+                Some(_) => {
+                    self.error(ErrorKind::Expected("`,`, a line break or `]`"), (self.pos, self.pos));
+                    self.skip_until(token::Kind::RBracket);
+                    break;
+                }
+                None => break,
+            }
+        }
+        self.nesting -= 1;
+        self.expect(token::Kind::RBracket, "`]`");
+        selection
+    }
+
+    /// The body of loops, it has to be a block
+    fn parse_block(&mut self) -> AstIdx {
+        if self.peak_kind(token::Kind::LBrace) {
+            self.parse_atom()
+        } else {
+            self.error_node(ErrorKind::Expected("a block"), self.pos)
+        }
+    }
+
+    /// The value after `return`, `break` or `continue`, that is only there if it is in the same line
+    fn parse_optional_value(&mut self) -> Option<AstIdx> {
+        let ends = |t: Token| {
+            matches!(t.kind, token::Kind::RParen | token::Kind::RBracket | token::Kind::RBrace | token::Kind::Comma)
+        };
+        let has_value = self.continues_expression() && !self.peak().is_some_and(ends);
+        has_value.then(|| self.parse_expresion(0))
     }
 
     fn parse_binary_op(&mut self, lhs: AstIdx, precedence: usize) -> AstIdx {
@@ -164,26 +1031,41 @@ impl Parser {
         let _enter = span.enter();
         let mut lhs = lhs;
         while let Some(token) = self.peak() {
-            let op_precedence = match token.kind {
-                token::Kind::ColonEqual | token::Kind::Equal => 1,
-                token::Kind::Plus | token::Kind::Minus => 10,
-                token::Kind::Multiply | token::Kind::Divide => 20,
-                token::Kind::LessThan
-                | token::Kind::GreaterThan
-                | token::Kind::LessThanOrEqual
-                | token::Kind::GreaterThanOrEqual => 30,
-                token::Kind::EqualEqual | token::Kind::NotEqual => 40,
-                token::Kind::Dot | token::Kind::Arrow => 50,
-                token::Kind::ColonColon => 60,
-                _ => break,
+            // An operator at the start of a line starts a new statement, to continue an
+            // expression in the next line leave the operator at the end of the previous one
+            if !self.continues_expression() {
+                break;
+            }
+            if matches!(token.kind, token::Kind::Colon | token::Kind::ColonEqual) {
+                if ASSIGNMENT_PRECEDENCE < precedence {
+                    break;
+                }
+                lhs = self.parse_declaration(lhs);
+                continue;
+            }
+            let Some((op_precedence, associativity)) = infix_precedence(token.kind) else {
+                break;
             };
-
             if op_precedence < precedence {
                 break;
             }
+            if token.kind == token::Kind::Is {
+                self.consume();
+                let pattern = self.parse_pattern();
+                lhs = self.save_ast(Node {
+                    kind: Kind::Is { value: lhs, pattern },
+                    span: (self.get(lhs).span.0, self.pos),
+                });
+                continue;
+            }
 
             let op = self.consume().kind; // consume operator
-            let rhs = self.parse_atom();
+            // The right side of a left associative operator only takes operators that bind tighter,
+            // a right associative one also takes the ones at its own level
+            let rhs = match associativity {
+                Associativity::Left => self.parse_expresion(op_precedence + 1),
+                Associativity::Right => self.parse_expresion(op_precedence),
+            };
             let ast = Node {
                 kind: Kind::BinaryOp { lhs, rhs, op },
                 span: (self.get(lhs).span.0, self.get(rhs).span.1),
@@ -193,25 +1075,794 @@ impl Parser {
         lhs
     }
 
+    /// The rest of a declaration after its target, starting at the `:` or `:=`.
+    /// The type can be left out in both forms, `x : = 3` is the same as `x := 3`
+    fn parse_declaration(&mut self, target: AstIdx) -> AstIdx {
+        let destructures = matches!(
+            self.get(target).kind,
+            Kind::Tuple { .. } | Kind::Variant { .. } | Kind::Construct { .. } | Kind::Pattern(_)
+        );
+        let target = if destructures { self.pattern_from(target) } else { target };
+        let ty = match self.consume().kind {
+            token::Kind::Colon if !self.peak_kind(token::Kind::Equal) => Some(self.parse_type(ASSIGNMENT_PRECEDENCE + 1)),
+            _ => None,
+        };
+        let has_value = ty.is_none() || (self.continues_expression() && self.peak_kind(token::Kind::Equal));
+        let value = has_value.then(|| {
+            self.eat(token::Kind::Equal);
+            if destructures {
+                // Like in a match arm an `and` after the value is a condition that can use the bound
+                // names, `if [Cat::[name] or Dog::[name]] := animal and name <> "" {`
+                self.parse_expresion(AND_PRECEDENCE + 1)
+            } else {
+                // Like assignments declarations are right associative, `a := b := 0`
+                self.parse_expresion(ASSIGNMENT_PRECEDENCE)
+            }
+        });
+        let otherwise = (value.is_some() && self.continues_expression() && self.eat(token::Kind::Else))
+            .then(|| self.parse_block());
+        self.save_ast(Node {
+            kind: Kind::Declaration {
+                target,
+                ty,
+                value,
+                otherwise,
+                public: false,
+            },
+            span: (self.get(target).span.0, self.pos),
+        })
+    }
+
+    fn parse_type(&mut self, precedence: usize) -> AstIdx {
+        let in_type = std::mem::replace(&mut self.in_type, true);
+        let ty = self.parse_expresion(precedence);
+        self.in_type = in_type;
+        ty
+    }
+
+    /// Everything between `[` and `]`: tuples, records, arrays and variants. The elements are
+    /// separated by commas or line breaks, and `or` between them makes a variant.
+    fn parse_brackets(&mut self, start: TokenIdx) -> AstIdx {
+        self.nesting += 1;
+        let mut elements = Vec::new();
+        let mut malformed = false;
+        while !malformed && self.peak().is_some_and(|t| t.kind != token::Kind::RBracket) {
+            let element_start = self.pos;
+            elements.push(self.parse_bracket_element());
+            if self.pos == element_start && !self.peak_kind(token::Kind::Comma) {
+                // Not even an expression, like a stray closer, the error is already reported
+                self.skip_until(token::Kind::RBracket);
+                malformed = true;
+                continue;
+            }
+            if self.eat(token::Kind::Comma) {
+                continue;
+            }
+            match self.peak() {
+                Some(token) if token.kind == token::Kind::RBracket || token.newline_before => {}
+                Some(_) => {
+                    self.error(ErrorKind::Expected("`,`, a line break or `]`"), (self.pos, self.pos));
+                    self.skip_until(token::Kind::RBracket);
+                    malformed = true;
+                }
+                None => break,
+            }
+        }
+        self.nesting -= 1;
+        // After an error only the bracket we were looking for is consumed, not to report it twice
+        if !self.eat(token::Kind::RBracket) && !malformed {
+            self.error(ErrorKind::Expected("`]`"), (self.pos, self.pos));
+        }
+
+        match *elements.as_slice() {
+            [element] if matches!(self.get(element).kind, Kind::Variant { .. }) => {
+                self.nodes[element].span = (start, self.pos);
+                element
+            }
+            [element] if self.in_type => self.parse_array_type(start, element),
+            _ => self.save_ast(Node {
+                kind: Kind::Tuple { elements },
+                span: (start, self.pos),
+            }),
+        }
+    }
+
+    /// `a`, `name := a` or `a or b`
+    fn parse_bracket_element(&mut self) -> AstIdx {
+        let start = self.pos;
+        let mut alternatives = vec![self.parse_field()];
+        while self.eat(token::Kind::Or) {
+            alternatives.push(self.parse_field());
+        }
+        if alternatives.len() == 1 {
+            return alternatives[0];
+        }
+        self.save_ast(Node {
+            kind: Kind::Variant { alternatives },
+            span: (start, self.pos),
+        })
+    }
+
+    /// An element of a tuple that can have a name. Inside of brackets `or` separates the
+    /// alternatives of a variant, so it is not part of the expressions.
+    fn parse_field(&mut self) -> AstIdx {
+        let start = self.pos;
+        let name = self.parse_expresion(OR_PRECEDENCE + 1);
+        let (ty, value) = match self.peak().map(|t| t.kind) {
+            Some(token::Kind::Colon) => {
+                self.consume();
+                let ty = (!self.peak_kind(token::Kind::Equal)).then(|| self.parse_type(OR_PRECEDENCE + 1));
+                let value = self.eat(token::Kind::Equal).then(|| self.parse_expresion(OR_PRECEDENCE + 1));
+                (ty, value)
+            }
+            Some(token::Kind::ColonEqual | token::Kind::Equal) => {
+                self.consume();
+                (None, Some(self.parse_expresion(OR_PRECEDENCE + 1)))
+            }
+            _ => return name,
+        };
+        self.save_ast(Node {
+            kind: Kind::Field { name, ty, value },
+            span: (start, self.pos),
+        })
+    }
+
+    /// Turns `[Int * 3]` and `[3 * 3 * Int]` into array types, anything else is a tuple with one element
+    fn parse_array_type(&mut self, start: TokenIdx, element: AstIdx) -> AstIdx {
+        let mut factors = Vec::new();
+        let mut pending = vec![element];
+        while let Some(idx) = pending.pop() {
+            match self.get(idx).kind {
+                Kind::BinaryOp {
+                    lhs,
+                    rhs,
+                    op: token::Kind::Multiply,
+                } => pending.extend([rhs, lhs]),
+                _ => factors.push(idx),
+            }
+        }
+        let (sizes, types): (Vec<AstIdx>, Vec<AstIdx>) =
+            factors.into_iter().partition(|&idx| matches!(self.get(idx).kind, Kind::Number { .. }));
+        if sizes.is_empty() || types.is_empty() {
+            return self.save_ast(Node {
+                kind: Kind::Tuple { elements: vec![element] },
+                span: (start, self.pos),
+            });
+        }
+        if types.len() > 1 {
+            return self.error_node(ErrorKind::ArrayElementTypes, start);
+        }
+        for &size in &sizes {
+            if let Kind::Number {
+                literal: token::Literal::Real(_),
+                ..
+            } = self.get(size).kind
+            {
+                self.error(ErrorKind::ArraySize, self.get(size).span);
+            }
+        }
+        self.save_ast(Node {
+            kind: Kind::ArrayType {
+                element: types[0],
+                sizes,
+            },
+            span: (start, self.pos),
+        })
+    }
+
+    /// A parameter, `name : Type = default` or `args...: Args...`, or just an expression for the unnamed ones
+    fn parse_parameter(&mut self) -> AstIdx {
+        let start = self.pos;
+        let kinds = [0, 1, 2].map(|n| self.peak_nth(n).map(|t| t.kind));
+        let (name, variadic) = match kinds {
+            [Some(token::Kind::Identifier(name)), Some(token::Kind::Colon), _] => (name, false),
+            [Some(token::Kind::Identifier(name)), Some(token::Kind::Ellipsis), Some(token::Kind::Colon)] => (name, true),
+            _ => return self.parse_expresion(0),
+        };
+        self.consume(); // name
+        if variadic {
+            self.consume(); // ellipsis
+        }
+        self.consume(); // colon
+        let ty = self.parse_type(ASSIGNMENT_PRECEDENCE + 1);
+        let default = self.eat(token::Kind::Equal).then(|| self.parse_expresion(ASSIGNMENT_PRECEDENCE + 1));
+        self.save_ast(Node {
+            kind: Kind::Param {
+                name: Some(name),
+                ty: Some(ty),
+                default,
+                variadic,
+            },
+            span: (start, self.pos),
+        })
+    }
+
+    /// Elements of a parenthesized list after the opening parenthesis, and whether it had a trailing comma.
+    /// It can be the parameters of a function or just an expression in parentheses.
+    fn parse_parameters(&mut self) -> (Vec<AstIdx>, bool) {
+        self.nesting += 1;
+        let mut elements = Vec::new();
+        let mut trailing_comma = false;
+        while self.peak().is_some_and(|t| t.kind != token::Kind::RParen) {
+            elements.push(self.parse_parameter());
+            trailing_comma = self.eat(token::Kind::Comma);
+            if !trailing_comma {
+                break;
+            }
+        }
+        self.nesting -= 1;
+        self.expect(token::Kind::RParen, "`)`");
+        (elements, trailing_comma)
+    }
+
+    /// Generic parameters after the `<`, like `<A, B : Number, Args...>`
+    fn parse_generics(&mut self) -> Vec<AstIdx> {
+        self.nesting += 1;
+        let mut generics = Vec::new();
+        while let Some(token::Kind::Identifier(name)) = self.peak().map(|t| t.kind) {
+            let start = self.pos;
+            self.consume();
+            let variadic = self.eat(token::Kind::Ellipsis);
+            // The bound can not have comparisons in it, the `>` closes the list
+            let bound = self.eat(token::Kind::Colon).then(|| self.parse_type(COMPARISON_PRECEDENCE + 1));
+            generics.push(self.save_ast(Node {
+                kind: Kind::GenericParam { name, bound, variadic },
+                span: (start, self.pos),
+            }));
+            if !self.eat(token::Kind::Comma) {
+                break;
+            }
+        }
+        self.nesting -= 1;
+        self.expect(token::Kind::GreaterThan, "`>` to close the generic parameters");
+        generics
+    }
+
+    /// Tries to parse `<A, B>` right after a name as its generic arguments. When it is not one, like
+    /// the start of `a<b and c`, everything is undone so it can be parsed as a comparison. Unless a type
+    /// goes after them, like in `ref<Lifetime> V`, the arguments can not be followed by a name or a
+    /// literal either, so `f(a<b, c>d)` still has two comparisons.
+    fn parse_type_arguments(&mut self, followed_by_type: bool) -> Option<Vec<AstIdx>> {
+        let (pos, nodes, errors) = (self.pos, self.nodes.len(), self.errors.len());
+        let attempt = (pos, self.nesting > 0, self.in_type, followed_by_type);
+        if self.not_type_arguments.contains(&attempt) {
+            return None;
+        }
+        self.consume(); // <
+        self.nesting += 1;
+        let mut args = Vec::new();
+        loop {
+            // Like in the generic parameters the `>` closes the list
+            args.push(self.parse_type(COMPARISON_PRECEDENCE + 1));
+            if !self.eat(token::Kind::Comma) {
+                break;
+            }
+        }
+        self.nesting -= 1;
+        if self.errors.len() == errors && self.eat(token::Kind::GreaterThan) && (followed_by_type || self.ends_type_application()) {
+            return Some(args);
+        }
+        event!(tracing::Level::DEBUG, "Not generic arguments, backtracking to {}", pos);
+        self.pos = pos;
+        self.nodes.truncate(nodes);
+        self.errors.truncate(errors);
+        self.not_type_arguments.insert(attempt);
+        None
+    }
+
+    /// Whether the next token can go after a type with generic arguments
+    fn ends_type_application(&mut self) -> bool {
+        match self.peak() {
+            None => true,
+            Some(token) if token.newline_before => true,
+            Some(token) => matches!(
+                token.kind,
+                token::Kind::LParen
+                    | token::Kind::RParen
+                    | token::Kind::LBrace
+                    | token::Kind::RBrace
+                    | token::Kind::RBracket
+                    | token::Kind::ColonColon
+                    | token::Kind::Dot
+                    | token::Kind::GreaterThan
+                    | token::Kind::Comma
+                    | token::Kind::Colon
+                    | token::Kind::ColonEqual
+                    | token::Kind::Equal
+                    | token::Kind::Arrow
+                    | token::Kind::Ellipsis
+                    | token::Kind::Or
+                    | token::Kind::With
+            ),
+        }
+    }
+
+    /// Whether the next token is a `<` that starts right where the previous token ends. Generic
+    /// arguments are written without spaces, `Vec2<A>`, while comparisons have them, `a < b`.
+    fn type_arguments_follow(&mut self) -> bool {
+        let Some(next) = self.peak() else { return false };
+        let attached = self.pos.checked_sub(1).is_some_and(|previous| self.tokens[previous].span.end == next.span.start);
+        next.kind == token::Kind::LessThan && attached
+    }
+
+    /// The rest of a function after its parameters, starting at the `->`
+    fn parse_function(&mut self, start: TokenIdx, generics: Vec<AstIdx>, params: Vec<AstIdx>) -> AstIdx {
+        let params = params
+            .into_iter()
+            .map(|param| match self.get(param).kind {
+                Kind::Param { .. } => param,
+                _ => {
+                    let span = self.get(param).span;
+                    self.save_ast(Node {
+                        kind: Kind::Param {
+                            name: None,
+                            ty: Some(param),
+                            default: None,
+                            variadic: false,
+                        },
+                        span,
+                    })
+                }
+            })
+            .collect();
+        self.consume(); // ->
+
+        let mut effects = Vec::new();
+        let result_start = self.pos;
+        let result = match (self.continues_expression(), self.peak().map(|t| t.kind), self.peak_nth(1).map(|t| t.kind)) {
+            (false, ..) | (true, Some(token::Kind::LBrace), _) => None,
+            (true, Some(token::Kind::LParen), _) => {
+                self.consume();
+                let (mut elements, _) = self.parse_parameters();
+                if self.continues_expression() && self.peak_kind(token::Kind::Arrow) {
+                    Some(self.parse_function(result_start, Vec::new(), elements))
+                } else if elements.is_empty() {
+                    None
+                } else {
+                    // Inside of parentheses the effects go with the result, `-> (Result, Effect)`
+                    effects = elements.split_off(1);
+                    Some(elements[0])
+                }
+            }
+            (true, Some(token::Kind::Identifier(name)), Some(token::Kind::Colon)) => {
+                // Named result, `-> left: Vec2<A>`
+                self.consume();
+                self.consume();
+                let ty = self.parse_type(ARROW_PRECEDENCE);
+                Some(self.save_ast(Node {
+                    kind: Kind::Param {
+                        name: Some(name),
+                        ty: Some(ty),
+                        default: None,
+                        variadic: false,
+                    },
+                    span: (result_start, self.pos),
+                }))
+            }
+            (true, ..) => Some(self.parse_type(ARROW_PRECEDENCE)),
+        };
+        // Inside of parentheses the commas separate the parameters of the outer function
+        while self.nesting == 0 && self.continues_expression() && self.eat(token::Kind::Comma) {
+            effects.push(self.parse_type(ARROW_PRECEDENCE + 1));
+        }
+        let body = (self.continues_expression() && self.peak_kind(token::Kind::LBrace)).then(|| self.parse_atom());
+        self.save_ast(Node {
+            kind: Kind::Function {
+                generics,
+                params,
+                result,
+                effects,
+                body,
+            },
+            span: (start, self.pos),
+        })
+    }
+
+    /// Arguments of a call, after the opening parenthesis. A trailing comma is allowed.
+    fn parse_arguments(&mut self) -> Vec<AstIdx> {
+        self.nesting += 1;
+        let mut args = Vec::new();
+        while self.peak().is_some_and(|t| t.kind != token::Kind::RParen) {
+            args.push(self.parse_expresion(0));
+            if !self.eat(token::Kind::Comma) {
+                break;
+            }
+        }
+        self.nesting -= 1;
+        self.expect(token::Kind::RParen, "`,` or `)`");
+        args
+    }
+
+    /// A name or a path to it, like `print` or `std::print`
+    fn parse_path(&mut self) -> AstIdx {
+        let mut path = self.parse_atom();
+        while self.continues_expression() && self.eat(token::Kind::ColonColon) {
+            let rhs = self.parse_atom();
+            path = self.save_ast(Node {
+                kind: Kind::BinaryOp {
+                    lhs: path,
+                    rhs,
+                    op: token::Kind::ColonColon,
+                },
+                span: (self.get(path).span.0, self.pos),
+            });
+        }
+        path
+    }
+
+    /// Calls, member accesses, paths, generic arguments, spreads and effect handlers, they bind tighter
+    /// than any other operator.
+    /// So `f() with { ... } == x` compares the result of the handled call.
+    fn parse_postfix(&mut self, lhs: AstIdx) -> AstIdx {
+        let mut lhs = lhs;
+        while self.continues_expression() {
+            let start = self.get(lhs).span.0;
+            let Some(token) = self.peak() else { break };
+            let kind = match token.kind {
+                token::Kind::LParen => {
+                    self.consume();
+                    let args = self.parse_arguments();
+                    Kind::Call { callee: lhs, args }
+                }
+                token::Kind::Dot => {
+                    self.consume();
+                    let mut member = self.parse_path();
+                    if self.type_arguments_follow()
+                        && let Some(args) = self.parse_type_arguments(false)
+                    {
+                        member = self.save_ast(Node {
+                            kind: Kind::TypeApplication { ty: member, args },
+                            span: (self.get(member).span.0, self.pos),
+                        });
+                    }
+                    if self.continues_expression() && self.eat(token::Kind::LParen) {
+                        let args = self.parse_arguments();
+                        Kind::MethodCall {
+                            receiver: lhs,
+                            method: member,
+                            args,
+                        }
+                    } else {
+                        Kind::Member { object: lhs, member }
+                    }
+                }
+                token::Kind::ColonColon => {
+                    self.consume();
+                    let rhs = self.parse_atom();
+                    match self.get(rhs).kind {
+                        Kind::Tuple { .. } | Kind::Variant { .. } => Kind::Construct { ty: lhs, args: rhs },
+                        _ => Kind::BinaryOp {
+                            lhs,
+                            rhs,
+                            op: token::Kind::ColonColon,
+                        },
+                    }
+                }
+                token::Kind::LessThan if self.type_arguments_follow() => match self.parse_type_arguments(false) {
+                    Some(args) => Kind::TypeApplication { ty: lhs, args },
+                    None => break,
+                },
+                token::Kind::Ellipsis => {
+                    self.consume();
+                    Kind::Spread { expr: lhs }
+                }
+                token::Kind::With => {
+                    self.consume();
+                    if !self.eat(token::Kind::LBrace) {
+                        lhs = self.error_node(ErrorKind::Expected("`{` with the handlers"), start);
+                        continue;
+                    }
+                    let handlers = self.parse_arms(Self::parse_handler);
+                    Kind::Handle { expr: lhs, handlers }
+                }
+                _ => break,
+            };
+            lhs = self.save_ast(Node {
+                kind,
+                span: (start, self.pos),
+            });
+        }
+        lhs
+    }
+
     fn parse_expresion(&mut self, precedence: usize) -> AstIdx {
         let span = span!(tracing::Level::DEBUG, "parse_expresion", precedence = precedence);
         let _enter = span.enter();
         let lhs = self.parse_atom();
+        let lhs = self.parse_postfix(lhs);
         self.parse_binary_op(lhs, precedence)
     }
 
+    /// A statement ends at a line break, the `}` of its block or the end of the file, so `x := 1 2`
+    /// is an error instead of two statements
+    fn parse_statement(&mut self) -> AstIdx {
+        let (start, errors) = (self.pos, self.errors.len());
+        let statement = self.parse_expresion(0);
+        if self.pos == start {
+            // A stray closing delimiter, the error is already reported
+            self.consume();
+        } else if self.errors.len() == errors
+            && self.peak().is_some_and(|t| !t.newline_before && t.kind != token::Kind::RBrace) {
+            self.error(ErrorKind::Expected("a line break"), (self.pos, self.pos));
+        }
+        statement
+    }
+
     fn parse_program(mut self) -> Module {
         let mut definitions = Vec::new();
         while self.peak().is_some() {
-            definitions.push(self.parse_expresion(0));
+            definitions.push(self.parse_statement());
         }
         Module {
+            file: self.file,
             definitions,
+            errors: self.errors,
             ast: self.nodes,
+            tokens: self.tokens,
         }
     }
 }
 
-pub fn parse(tokens: Vec<Token>) -> Module {
-    Parser::new(tokens).parse_program()
+const ASSIGNMENT_PRECEDENCE: usize = 1;
+const ARROW_PRECEDENCE: usize = 5;
+const OR_PRECEDENCE: usize = 10;
+const AND_PRECEDENCE: usize = 20;
+const COMPARISON_PRECEDENCE: usize = 40;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Associativity {
+    Left,
+    Right,
+}
+
+/// Precedence of the infix operators, higher binds tighter. So `a := b or c == d + e * f` is
+/// `a := (b or (c == (d + (e * f))))`
+fn infix_precedence(kind: token::Kind) -> Option<(usize, Associativity)> {
+    let precedence = match kind {
+        // Declarations with `:` and `:=` are at this level too, see `parse_declaration`
+        token::Kind::Equal
+        | token::Kind::PlusEqual
+        | token::Kind::MinusEqual
+        | token::Kind::MultiplyEqual
+        | token::Kind::DivideEqual => return Some((ASSIGNMENT_PRECEDENCE, Associativity::Right)),
+        // Function types, `A -> B -> C` is a function that returns a function
+        token::Kind::Arrow => return Some((ARROW_PRECEDENCE, Associativity::Right)),
+        token::Kind::Or => OR_PRECEDENCE,
+        token::Kind::And => AND_PRECEDENCE,
+        token::Kind::EqualEqual | token::Kind::NotEqual | token::Kind::LessGreater => 30,
+        token::Kind::LessThan
+        | token::Kind::GreaterThan
+        | token::Kind::LessThanOrEqual
+        | token::Kind::GreaterThanOrEqual => COMPARISON_PRECEDENCE,
+        // The right side is a pattern, see `parse_pattern`
+        token::Kind::Is => 50,
+        token::Kind::DotDot => 60,
+        token::Kind::Plus | token::Kind::Minus => 70,
+        token::Kind::Multiply | token::Kind::Divide | token::Kind::Modulo => 80,
+        // Member access and paths are postfix, see `parse_postfix`
+        _ => return None,
+    };
+    Some((precedence, Associativity::Left))
+}
+
+/// Precedence of the operand of prefix operators. `not` takes comparisons, so `not a == b` is `not (a == b)`,
+/// and `assert` everything but assignments. The rest only take what binds tighter than any infix operator,
+/// so `-a.b * c` is `(-(a.b)) * c`.
+fn prefix_precedence(kind: token::Kind) -> Option<usize> {
+    match kind {
+        token::Kind::Minus | token::Kind::Bang | token::Kind::Ref | token::Kind::Mut => Some(90),
+        token::Kind::Not => Some(25),
+        token::Kind::Assert => Some(ASSIGNMENT_PRECEDENCE + 1),
+        _ => None,
+    }
+}
+
+pub fn parse(file: FileId, tokens: impl IntoIterator<Item = Token>) -> Module {
+    Parser::new(file, tokens.into_iter()).parse_program()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::SourceMap;
+
+    fn parse_text(text: &str) -> Module {
+        let interner = token::Interner::default();
+        let mut sources = SourceMap::default();
+        let id = sources.add("test", text);
+        let lexed = token::parse(sources.get(id), Default::default(), &interner);
+        assert!(lexed.errors.is_empty(), "{}: lexing errors {:?}", text, lexed.errors);
+        parse(id, lexed.tokens)
+    }
+
+    /// Parses a single statement and prints its tree fully parenthesized, like `(+ 1 (* 2 3))`
+    fn dump(text: &str) -> String {
+        let module = parse_text(text);
+        assert!(module.errors.is_empty(), "{}: parsing errors {:?}", text, module.errors);
+        assert_eq!(module.definitions.len(), 1, "{}", text);
+        Dump { module: &module, text }.node(module.definitions[0])
+    }
+
+    struct Dump<'a> {
+        module: &'a Module,
+        text: &'a str,
+    }
+
+    impl Dump<'_> {
+        fn source(&self, span: (TokenIdx, TokenIdx)) -> &str {
+            let span = self.module.token_span(span);
+            &self.text[span.start..span.end]
+        }
+
+        fn node(&self, idx: AstIdx) -> String {
+            let node = self.module.get(idx);
+            match node.kind {
+                Kind::BinaryOp { lhs, rhs, .. } => {
+                    let op = self.module.get(lhs).span.1;
+                    format!("({} {} {})", self.source((op, op + 1)), self.node(lhs), self.node(rhs))
+                }
+                Kind::UnaryOp { expr, .. } => {
+                    format!("({} {})", self.source((node.span.0, node.span.0 + 1)), self.node(expr))
+                }
+                Kind::Declaration { target, ty: None, value: Some(value), otherwise: None, public: false } => {
+                    format!("(:= {} {})", self.node(target), self.node(value))
+                }
+                Kind::Is { value, pattern } => format!("(is {} {})", self.node(value), self.node(pattern)),
+                Kind::Member { object, member } => format!("(. {} {})", self.node(object), self.node(member)),
+                Kind::Call { callee, ref args } => {
+                    let args: Vec<_> = args.iter().map(|&arg| self.node(arg)).collect();
+                    format!("(call {} [{}])", self.node(callee), args.join(" "))
+                }
+                Kind::MethodCall { receiver, method, ref args } => {
+                    let args: Vec<_> = args.iter().map(|&arg| self.node(arg)).collect();
+                    format!("(.call {} {} [{}])", self.node(receiver), self.node(method), args.join(" "))
+                }
+                Kind::Pattern(Pattern::Or { ref alternatives }) => {
+                    let alternatives: Vec<_> = alternatives.iter().map(|&alternative| self.node(alternative)).collect();
+                    format!("(or {})", alternatives.join(" "))
+                }
+                Kind::Pattern(Pattern::Tuple { ref elements }) => {
+                    let elements: Vec<_> = elements.iter().map(|&element| self.node(element)).collect();
+                    format!("[{}]", elements.join(" "))
+                }
+                Kind::Pattern(Pattern::Variant { path, payload }) => match payload {
+                    Some(payload) => format!("(variant {} {})", self.node(path), self.node(payload)),
+                    None => format!("(variant {})", self.node(path)),
+                },
+                Kind::If { cond, if_branch, else_branch: None } => {
+                    format!("(if {} {})", self.node(cond), self.node(if_branch))
+                }
+                Kind::Block { ref statements } => {
+                    let statements: Vec<_> = statements.iter().map(|&statement| self.node(statement)).collect();
+                    format!("{{{}}}", statements.join(" "))
+                }
+                Kind::Group { expr } => self.node(expr),
+                Kind::Number { .. }
+                | Kind::Bool { .. }
+                | Kind::Text { .. }
+                | Kind::Identifier { .. }
+                | Kind::Pattern(Pattern::Binding { .. }) => self.source(node.span).to_string(),
+                ref kind => panic!("no dump for {:?}", kind),
+            }
+        }
+    }
+
+    #[test]
+    fn assignment_is_right_associative() {
+        assert_eq!(dump("a := b := c"), "(:= a (:= b c))");
+        assert_eq!(dump("a = b = c"), "(= a (= b c))");
+        assert_eq!(dump("a += b -= c"), "(+= a (-= b c))");
+        assert_eq!(dump("a = b or c"), "(= a (or b c))");
+        assert_eq!(dump("a := b = c"), "(:= a (= b c))");
+    }
+
+    #[test]
+    fn arrow_is_right_associative() {
+        assert_eq!(dump("Int -> Int -> Int"), "(-> Int (-> Int Int))");
+        assert_eq!(dump("f = Int -> Int"), "(= f (-> Int Int))");
+        assert_eq!(dump("A or B -> C"), "(-> (or A B) C)");
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(dump("x or y and z"), "(or x (and y z))");
+        assert_eq!(dump("x and y or z"), "(or (and x y) z)");
+        assert_eq!(dump("a or b or c"), "(or (or a b) c)");
+        assert_eq!(dump("not a and b"), "(and (not a) b)");
+        assert_eq!(dump("not a == b"), "(not (== a b))");
+    }
+
+    #[test]
+    fn ordering_binds_tighter_than_equality() {
+        assert_eq!(dump("a < b == c <= d"), "(== (< a b) (<= c d))");
+        assert_eq!(dump("a <> b == c"), "(== (<> a b) c)");
+        assert_eq!(dump("a != b and c > d"), "(and (!= a b) (> c d))");
+        assert_eq!(dump("a >= b + 1"), "(>= a (+ b 1))");
+    }
+
+    #[test]
+    fn is_takes_a_pattern_up_to_and() {
+        assert_eq!(dump("x is Int and y is Text"), "(and (is x Int) (is y Text))");
+        // The pattern takes everything up to `and`, including `or` between alternatives
+        assert_eq!(dump("x is A or B and c"), "(and (is x (or A B)) c)");
+        assert_eq!(dump("x + 1 is n"), "(is (+ x 1) n)");
+        assert_eq!(dump("a < b is n"), "(< a (is b n))");
+    }
+
+    #[test]
+    fn range_is_between_is_and_arithmetic() {
+        assert_eq!(dump("0..n + 1"), "(.. 0 (+ n 1))");
+        assert_eq!(dump("a * 2..b"), "(.. (* a 2) b)");
+        assert_eq!(dump("x < 0..n"), "(< x (.. 0 n))");
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(dump("1 + 2 * 3"), "(+ 1 (* 2 3))");
+        assert_eq!(dump("1 * 2 + 3"), "(+ (* 1 2) 3)");
+        assert_eq!(dump("a - b - c"), "(- (- a b) c)");
+        assert_eq!(dump("a * b % c"), "(% (* a b) c)");
+        assert_eq!(dump("a / b * c"), "(* (/ a b) c)");
+        assert_eq!(dump("(a + b) * c"), "(* (+ a b) c)");
+    }
+
+    #[test]
+    fn prefix_operators() {
+        assert_eq!(dump("-a * b"), "(* (- a) b)");
+        assert_eq!(dump("-a.b * c"), "(* (- (. a b)) c)");
+        assert_eq!(dump("!a or b"), "(or (! a) b)");
+        assert_eq!(dump("a - -b"), "(- a (- b))");
+    }
+
+    #[test]
+    fn postfix_binds_tightest() {
+        assert_eq!(dump("a.b.c + 1"), "(+ (. (. a b) c) 1)");
+        assert_eq!(dump("a.b(c) * 2"), "(* (.call a b [c]) 2)");
+        assert_eq!(dump("f(x).y"), "(. (call f [x]) y)");
+        assert_eq!(dump("69_420.std::print"), "(. 69_420 (:: std print))");
+        assert_eq!(dump("std::math::pi * 2"), "(* (:: (:: std math) pi) 2)");
+    }
+
+    #[test]
+    fn nested_type_argument_attempts_are_not_repeated() {
+        // Every `<` here starts an attempt at generic arguments that fails, retrying them after
+        // each backtrack used to take exponential time
+        let depth = 40;
+        let unclosed = format!("x := {}", "a<(".repeat(depth));
+        assert!(!parse_text(&unclosed).errors.is_empty());
+        let closed = format!("x := {}1{}", "a<f(".repeat(depth), ")".repeat(depth));
+        assert!(parse_text(&closed).errors.is_empty());
+        let nested = format!("{}Int{}", "Vec<".repeat(depth), ">".repeat(depth));
+        let module = parse_text(&nested);
+        assert!(module.errors.is_empty());
+        assert!(matches!(module.get(module.definitions[0]).kind, Kind::TypeApplication { .. }));
+    }
+
+    #[test]
+    fn statements_end_at_line_breaks() {
+        let line_break = |token| [Error { kind: ErrorKind::Expected("a line break"), span: (token, token) }];
+        assert_eq!(parse_text("x := 1 2").errors, line_break(3));
+        assert_eq!(parse_text("{ a b }").errors, line_break(2));
+        assert!(parse_text("x := 1\n2").errors.is_empty());
+        assert!(parse_text("{ a }").errors.is_empty());
+        assert_eq!(parse_text("a\n-b").definitions.len(), 2);
+    }
+
+    #[test]
+    fn destructuring_value_stops_before_and() {
+        assert_eq!(
+            dump("if [Cat::[name] or Dog::[name]] := animal and name <> \"\" { name }"),
+            "(if (and (:= (or (variant Cat [name]) (variant Dog [name])) animal) (<> name \"\")) {name})"
+        );
+        assert_eq!(dump("[a, b] := pair and a > b"), "(and (:= [a b] pair) (> a b))");
+        // Other declarations still take the whole expression
+        assert_eq!(dump("ok := a and b"), "(:= ok (and a b))");
+    }
+
+    #[test]
+    fn parameters_without_a_result() {
+        assert_eq!(parse_text("x := (a: Int)").errors, [Error { kind: ErrorKind::MissingResult, span: (2, 7) }]);
+        assert_eq!(parse_text("f := <A>(x: A)").errors, [Error { kind: ErrorKind::MissingResult, span: (2, 10) }]);
+        assert!(parse_text("f := <A>(x: A) -> A").errors.is_empty());
+    }
 }
@@ -1,13 +1,51 @@
+use tracing::event;
 use tracing_appender::non_blocking::WorkerGuard;
 
 mod token;
 mod ast;
 mod pretty;
+mod source;
 
-fn compile_file(path: &str) -> ast::Module {
-    let (tokens, interner) = token::parse(&std::fs::read_to_string(path).expect("Failed to read file"));
-    ast::parse(tokens)
-    
+fn compile_file(sources: &source::SourceMap, file: source::FileId, edition: token::Edition, interner: &token::Interner) -> ast::Module {
+    let file = sources.get(file);
+    let options = token::Options { edition, ..Default::default() };
+    // The parser pulls the tokens as it goes, so they are never all buffered before parsing
+    let mut lexer = token::Lexer::new(&file.text, file.id, options, interner);
+    let module = ast::parse(file.id, lexer.by_ref());
+    let (errors, _) = lexer.finish();
+    for error in &errors {
+        let help = error.suggestion.map(|fix| match fix.replacement {
+            "" => fix.message.to_string(),
+            replacement => format!("{}: `{}`", fix.message, replacement),
+        });
+        report(sources, error.span, error.kind, help);
+    }
+    for error in &module.errors {
+        report(sources, module.token_span(error.span), error.kind, None);
+    }
+    for definition in &module.definitions {
+        event!(tracing::Level::DEBUG, "Definition at {}", sources.describe(module.span(*definition)));
+    }
+    module
+}
+
+/// Prints an error pointing at the offending source line
+fn report(sources: &source::SourceMap, span: source::Span, message: impl std::fmt::Display, help: Option<String>) {
+    let file = sources.get(span.file);
+    let start = file.location(span.start);
+    let end = file.location(span.end);
+    let line = file.line(start.line);
+    let width = if end.line == start.line { (end.column - start.column).max(1) } else { 1 };
+    let gutter = " ".repeat((start.line + 1).to_string().len());
+    eprintln!("error: {}", message);
+    eprintln!("{}--> {}", gutter, sources.describe(span));
+    eprintln!("{} |", gutter);
+    eprintln!("{} | {}", start.line + 1, line);
+    eprintln!("{} | {}{}", gutter, " ".repeat(start.column), "^".repeat(width));
+    if let Some(help) = help {
+        eprintln!("{} = help: {}", gutter, help);
+    }
+    eprintln!();
 }
 
 fn setup_logging() -> WorkerGuard {
@@ -28,9 +66,19 @@ fn setup_logging() -> WorkerGuard {
 fn main() {
     let  _guard = setup_logging();
 
-    let (tokens, interner) = token::parse("res := 3*if x >10 { return x} else { return 0 } + 2");
-
-    let module = ast::parse(tokens);
+    let mut sources = source::SourceMap::default();
+    let mut args = std::env::args().skip(1).peekable();
+    let edition = match args.next_if(|arg| arg == "--edition") {
+        Some(_) => args.next().unwrap_or_default().parse().unwrap_or_else(|error| panic!("{}", error)),
+        None => token::Edition::default(),
+    };
+    let file = match args.next() {
+        Some(path) => sources.load(&path).expect("Failed to read file"),
+        None => sources.add("<demo>", "res := 3*if x >10 { return x} else { return 0 } + 2"),
+    };
+    // One interner for the whole session, so symbols from different files can be compared
+    let interner = token::Interner::default();
+    let module = compile_file(&sources, file, edition, &interner);
 
     println!("{}", pretty::print(&module, &interner));
 }
@@ -2,12 +2,12 @@ use std::fmt::Write;
 
 use crate::{
     ast::{self, AstIdx, Module},
-    token::{self, Interner},
+    token::{self, Interner, Strings},
 };
 
 struct Printer<'a> {
     module: &'a ast::Module,
-    interner: &'a Interner,
+    strings: &'a Strings,
     buffer: String,
 }
 
@@ -17,25 +17,188 @@ impl<'a> Printer<'a> {
     }
 
     fn print_op(&mut self, op: &token::Kind) -> std::fmt::Result {
-        match op {
-            token::Kind::Plus => write!(self.buffer, "+"),
-            token::Kind::Minus => write!(self.buffer, "-"),
-            token::Kind::Multiply => write!(self.buffer, "*"),
-            token::Kind::Divide => write!(self.buffer, "/"),
-            token::Kind::Equal => write!(self.buffer, "="),
-            token::Kind::ColonEqual => write!(self.buffer, ":="),
-            token::Kind::EqualEqual => write!(self.buffer, "=="),
-            token::Kind::NotEqual => write!(self.buffer, "!="),
-            token::Kind::LessThan => write!(self.buffer, "<"),
-            token::Kind::GreaterThan => write!(self.buffer, ">"),
-            token::Kind::LessThanOrEqual => write!(self.buffer, "<="),
-            token::Kind::GreaterThanOrEqual => write!(self.buffer, ">="),
-            _ => unreachable!("We should only have operators here!"), 
+        let op = match op {
+            token::Kind::Plus => "+",
+            token::Kind::Minus => "-",
+            token::Kind::Multiply => "*",
+            token::Kind::Divide => "/",
+            token::Kind::Modulo => "%",
+            token::Kind::Equal => "=",
+            token::Kind::ColonEqual => ":=",
+            token::Kind::EqualEqual => "==",
+            token::Kind::NotEqual => "!=",
+            token::Kind::LessGreater => "<>",
+            token::Kind::LessThan => "<",
+            token::Kind::GreaterThan => ">",
+            token::Kind::LessThanOrEqual => "<=",
+            token::Kind::GreaterThanOrEqual => ">=",
+            token::Kind::PlusEqual => "+=",
+            token::Kind::MinusEqual => "-=",
+            token::Kind::MultiplyEqual => "*=",
+            token::Kind::DivideEqual => "/=",
+            token::Kind::Colon => ":",
+            token::Kind::ColonColon => "::",
+            token::Kind::Arrow => "->",
+            token::Kind::Comma => ",",
+            token::Kind::Dot => ".",
+            token::Kind::DotDot => "..",
+            token::Kind::Ellipsis => "...",
+            token::Kind::Pipe => "|",
+            token::Kind::Bang => "!",
+            token::Kind::And => "and",
+            token::Kind::Or => "or",
+            token::Kind::Not => "not",
+            token::Kind::Is => "is",
+            token::Kind::Ref => "ref",
+            token::Kind::Mut => "mut",
+            token::Kind::Assert => "assert",
+            _ => unreachable!("We should only have operators here!"),
+        };
+        write!(self.buffer, "{}", op)
+    }
+
+    /// Prints the content of a text literal, escaping what needs to be escaped
+    fn print_text(&mut self, text: token::Symbol) {
+        let Some(text) = self.strings.resolve(text) else {
+            self.buffer.push_str("<Unknown text>");
+            return;
+        };
+        for c in text.chars() {
+            match c {
+                '"' | '\\' | '{' | '}' => {
+                    self.buffer.push('\\');
+                    self.buffer.push(c);
+                }
+                '\n' => self.buffer.push_str("\\n"),
+                '\t' => self.buffer.push_str("\\t"),
+                '\r' => self.buffer.push_str("\\r"),
+                '\0' => self.buffer.push_str("\\0"),
+                _ => self.buffer.push(c),
+            }
+        }
+    }
+
+    /// Prints the nodes separated by commas
+    fn print_list(&mut self, nodes: &[AstIdx], indent: usize) -> std::fmt::Result {
+        for (i, node) in nodes.iter().enumerate() {
+            if i > 0 {
+                self.buffer.push_str(", ");
+            }
+            self.print_node(*node, indent)?;
+        }
+        Ok(())
+    }
+
+    fn print_arguments(&mut self, args: &[AstIdx], indent: usize) -> std::fmt::Result {
+        self.buffer.push('(');
+        self.print_list(args, indent)?;
+        self.buffer.push(')');
+        Ok(())
+    }
+
+    /// The arms of a match or the handlers of a `with`, one per line
+    fn print_arms(&mut self, arms: &[AstIdx], indent: usize) -> std::fmt::Result {
+        self.buffer.push_str("{\n");
+        for arm in arms {
+            self.indent(indent + 2);
+            self.print_node(*arm, indent + 2)?;
+            self.buffer.push('\n');
+        }
+        self.indent(indent);
+        self.buffer.push('}');
+        Ok(())
+    }
+
+    /// The optional value of `return`, `break` and `continue`
+    fn print_value(&mut self, value: Option<AstIdx>, indent: usize) -> std::fmt::Result {
+        if let Some(value) = value {
+            self.buffer.push(' ');
+            self.print_node(value, indent)?;
+        }
+        Ok(())
+    }
+
+    fn print_name(&mut self, name: token::Symbol) -> std::fmt::Result {
+        match self.strings.resolve(name) {
+            Some(name) => write!(self.buffer, "{}", name),
+            None => write!(self.buffer, "<Unknown identifier>"),
+        }
+    }
+
+    /// The path of an import without the `import` keyword, the same for the ones in a selection
+    fn print_import(&mut self, import: AstIdx) -> std::fmt::Result {
+        let ast::Kind::Import { path, selection } = &self.module.get(import).kind else {
+            return self.print_node(import, 0);
+        };
+        for (i, name) in path.iter().enumerate() {
+            if i > 0 {
+                self.buffer.push_str("::");
+            }
+            self.print_name(*name)?;
+        }
+        if let Some(selection) = selection {
+            self.buffer.push_str("::[");
+            for (i, nested) in selection.iter().enumerate() {
+                if i > 0 {
+                    self.buffer.push_str(", ");
+                }
+                self.print_import(*nested)?;
+            }
+            self.buffer.push(']');
+        }
+        Ok(())
+    }
+
+    fn print_pattern(&mut self, pattern: &ast::Pattern, indent: usize) -> std::fmt::Result {
+        match pattern {
+            ast::Pattern::Wildcard => self.buffer.push('_'),
+            ast::Pattern::Binding { name } => self.print_name(*name)?,
+            ast::Pattern::Literal { value } => self.print_node(*value, indent)?,
+            ast::Pattern::Tuple { elements } => {
+                self.buffer.push('[');
+                self.print_list(elements, indent)?;
+                self.buffer.push(']');
+            }
+            ast::Pattern::Field { pattern, field } => {
+                self.print_node(*pattern, indent)?;
+                self.buffer.push('=');
+                self.print_name(*field)?;
+            }
+            ast::Pattern::Variant { path, payload } => {
+                self.print_node(*path, indent)?;
+                if let Some(payload) = payload {
+                    self.buffer.push_str("::");
+                    self.print_node(*payload, indent)?;
+                }
+            }
+            ast::Pattern::Or { alternatives } => {
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    if i > 0 {
+                        self.buffer.push_str(" or ");
+                    }
+                    self.print_node(*alternative, indent)?;
+                }
+            }
+            ast::Pattern::Typed { pattern, ty } => {
+                self.print_node(*pattern, indent)?;
+                self.buffer.push_str(" : ");
+                self.print_node(*ty, indent)?;
+            }
         }
+        Ok(())
     }
 
     fn print_node(&mut self, node: AstIdx, indent: usize) -> std::fmt::Result {
         match &self.module.get(node).kind {
+            ast::Kind::BinaryOp {
+                lhs,
+                rhs,
+                op: token::Kind::ColonColon,
+            } => {
+                self.print_node(*lhs, indent)?;
+                self.buffer.push_str("::");
+                self.print_node(*rhs, indent)?;
+            }
             ast::Kind::BinaryOp { lhs, rhs, op } => {
                 self.print_node(*lhs, indent)?;
                 self.buffer.push(' ');
@@ -45,46 +208,134 @@ impl<'a> Printer<'a> {
             }
             ast::Kind::UnaryOp { expr, op } => {
                 self.print_op(op)?;
+                if matches!(op, token::Kind::Not | token::Kind::Ref | token::Kind::Mut | token::Kind::Assert) {
+                    self.buffer.push(' ');
+                }
                 self.print_node(*expr, indent)?;
             }
-            ast::Kind::Call { callee, args } => {
-                self.print_node(*callee, indent)?;
+            ast::Kind::Operator { op } => {
                 self.buffer.push('(');
-                for (i, arg) in args.iter().enumerate() {
-                    self.print_node(*arg, indent)?;
-                    if i > 0 {
-                        self.buffer.push_str(", ");
-                    }
-                }
+                self.print_op(op)?;
                 self.buffer.push(')');
             }
+            ast::Kind::Call { callee, args } => {
+                self.print_node(*callee, indent)?;
+                self.print_arguments(args, indent)?;
+            }
+            ast::Kind::Member { object, member } => {
+                self.print_node(*object, indent)?;
+                self.buffer.push('.');
+                self.print_node(*member, indent)?;
+            }
+            ast::Kind::MethodCall { receiver, method, args } => {
+                self.print_node(*receiver, indent)?;
+                self.buffer.push('.');
+                self.print_node(*method, indent)?;
+                self.print_arguments(args, indent)?;
+            }
             ast::Kind::Function {
+                generics,
                 params,
                 result,
+                effects,
                 body,
             } => {
-                self.buffer.push_str("(");
-                for (i, param) in params.iter().enumerate() {
-                    self.print_node(*param, indent)?;
-                    if i > 0 {
+                if !generics.is_empty() {
+                    self.buffer.push('<');
+                    self.print_list(generics, indent)?;
+                    self.buffer.push('>');
+                }
+                self.buffer.push('(');
+                self.print_list(params, indent)?;
+                self.buffer.push_str(") ->");
+                // With parentheses it reads the same inside of a parameter list
+                match (result, effects.is_empty()) {
+                    (Some(result), true) => {
+                        self.buffer.push(' ');
+                        self.print_node(*result, indent)?;
+                    }
+                    (Some(result), false) => {
+                        self.buffer.push_str(" (");
+                        self.print_node(*result, indent)?;
                         self.buffer.push_str(", ");
+                        self.print_list(effects, indent)?;
+                        self.buffer.push(')');
                     }
+                    (None, _) => {}
+                }
+                if let Some(body) = body {
+                    self.buffer.push(' ');
+                    self.print_node(*body, indent)?;
                 }
-                self.buffer.push_str(") -> ");
-                self.print_node(*result, indent)?;
-                self.buffer.push(' ');
-                self.print_node(*body, indent)?;
+            }
+            ast::Kind::Param {
+                name,
+                ty,
+                default,
+                variadic,
+            } => {
+                if let Some(name) = name {
+                    self.print_name(*name)?;
+                    if *variadic {
+                        self.buffer.push_str("...");
+                    }
+                    self.buffer.push_str(": ");
+                }
+                if let Some(ty) = ty {
+                    self.print_node(*ty, indent)?;
+                }
+                if let Some(default) = default {
+                    self.buffer.push_str(" = ");
+                    self.print_node(*default, indent)?;
+                }
+            }
+            ast::Kind::GenericParam { name, bound, variadic } => {
+                self.print_name(*name)?;
+                if *variadic {
+                    self.buffer.push_str("...");
+                }
+                if let Some(bound) = bound {
+                    self.buffer.push_str(": ");
+                    self.print_node(*bound, indent)?;
+                }
+            }
+            ast::Kind::GenericType { generics, ty } => {
+                self.buffer.push('<');
+                self.print_list(generics, indent)?;
+                self.buffer.push('>');
+                self.print_node(*ty, indent)?;
+            }
+            ast::Kind::TypeApplication { ty, args } => {
+                self.print_node(*ty, indent)?;
+                self.buffer.push('<');
+                self.print_list(args, indent)?;
+                self.buffer.push('>');
+            }
+            ast::Kind::Spread { expr } => {
+                self.print_node(*expr, indent)?;
+                self.buffer.push_str("...");
+            }
+            ast::Kind::Reference { lifetime, ty } => {
+                self.buffer.push_str("ref<");
+                self.print_node(*lifetime, indent)?;
+                self.buffer.push_str("> ");
+                self.print_node(*ty, indent)?;
             }
             ast::Kind::Block { statements } => {
                 self.buffer.push_str("{\n");
-                self.indent(indent + 2);
                 for statement in statements {
+                    self.indent(indent + 2);
                     self.print_node(*statement, indent + 2)?;
                     self.buffer.push('\n');
                 }
                 self.indent(indent);
                 self.buffer.push('}');
             }
+            ast::Kind::Group { expr } => {
+                self.buffer.push('(');
+                self.print_node(*expr, indent)?;
+                self.buffer.push(')');
+            }
             ast::Kind::If {
                 cond,
                 if_branch,
@@ -103,29 +354,192 @@ impl<'a> Printer<'a> {
                 self.buffer.push_str("loop ");
                 self.print_node(*body, indent)?;
             }
-            ast::Kind::Import => {
-                self.buffer.push_str("import");
+            ast::Kind::Match { scrutinee, arms } => {
+                self.buffer.push_str("match ");
+                self.print_node(*scrutinee, indent)?;
+                self.buffer.push(' ');
+                self.print_arms(arms, indent)?;
+            }
+            ast::Kind::MatchArm { pattern, guard, body } => {
+                self.print_node(*pattern, indent)?;
+                if let Some(guard) = guard {
+                    self.buffer.push_str(" and ");
+                    self.print_node(*guard, indent)?;
+                }
+                self.buffer.push_str(" -> ");
+                self.print_node(*body, indent)?;
+            }
+            ast::Kind::Effect { signature } => {
+                self.buffer.push_str("effect ");
+                self.print_node(*signature, indent)?;
+            }
+            ast::Kind::Raise { effect } => {
+                self.buffer.push_str("raise ");
+                self.print_node(*effect, indent)?;
+            }
+            ast::Kind::Handle { expr, handlers } => {
+                self.print_node(*expr, indent)?;
+                self.buffer.push_str(" with ");
+                self.print_arms(handlers, indent)?;
+            }
+            ast::Kind::Handler {
+                pattern,
+                continuation,
+                body,
+            } => {
+                self.print_node(*pattern, indent)?;
+                if let Some(continuation) = continuation {
+                    self.buffer.push_str(", ");
+                    self.print_name(*continuation)?;
+                }
+                self.buffer.push_str(" -> ");
+                self.print_node(*body, indent)?;
+            }
+            ast::Kind::For { binding, iter, body } => {
+                self.buffer.push_str("for ");
+                self.print_node(*binding, indent)?;
+                self.buffer.push_str(" in ");
+                self.print_node(*iter, indent)?;
+                self.buffer.push(' ');
+                self.print_node(*body, indent)?;
+            }
+            ast::Kind::Declaration {
+                target,
+                ty,
+                value,
+                otherwise,
+                public,
+            } => {
+                if *public {
+                    self.buffer.push_str("pub ");
+                }
+                // Without the brackets only the last alternative would be the target
+                if let ast::Kind::Pattern(ast::Pattern::Or { .. }) = self.module.get(*target).kind {
+                    self.buffer.push('[');
+                    self.print_node(*target, indent)?;
+                    self.buffer.push(']');
+                } else {
+                    self.print_node(*target, indent)?;
+                }
+                if let Some(ty) = ty {
+                    self.buffer.push_str(" : ");
+                    self.print_node(*ty, indent)?;
+                }
+                match (ty, value) {
+                    (Some(_), Some(value)) => {
+                        self.buffer.push_str(" = ");
+                        self.print_node(*value, indent)?;
+                    }
+                    (None, Some(value)) => {
+                        self.buffer.push_str(" := ");
+                        self.print_node(*value, indent)?;
+                    }
+                    (_, None) => {}
+                }
+                if let Some(otherwise) = otherwise {
+                    self.buffer.push_str(" else ");
+                    self.print_node(*otherwise, indent)?;
+                }
+            }
+            ast::Kind::Tuple { elements } => {
+                self.buffer.push('[');
+                self.print_list(elements, indent)?;
+                self.buffer.push(']');
+            }
+            ast::Kind::Field { name, ty, value } => {
+                self.print_node(*name, indent)?;
+                if let Some(ty) = ty {
+                    self.buffer.push_str(" : ");
+                    self.print_node(*ty, indent)?;
+                }
+                if let Some(value) = value {
+                    self.buffer.push_str(if ty.is_some() { " = " } else { " := " });
+                    self.print_node(*value, indent)?;
+                }
+            }
+            ast::Kind::Variant { alternatives } => {
+                self.buffer.push('[');
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    if i > 0 {
+                        self.buffer.push_str(" or ");
+                    }
+                    self.print_node(*alternative, indent)?;
+                }
+                self.buffer.push(']');
+            }
+            ast::Kind::ArrayType { element, sizes } => {
+                self.buffer.push('[');
+                self.print_node(*element, indent)?;
+                for size in sizes {
+                    self.buffer.push_str(" * ");
+                    self.print_node(*size, indent)?;
+                }
+                self.buffer.push(']');
+            }
+            ast::Kind::Construct { ty, args } => {
+                self.print_node(*ty, indent)?;
+                self.buffer.push_str("::");
+                self.print_node(*args, indent)?;
+            }
+            ast::Kind::Is { value, pattern } => {
+                self.print_node(*value, indent)?;
+                self.buffer.push_str(" is ");
+                self.print_node(*pattern, indent)?;
+            }
+            ast::Kind::Pattern(pattern) => self.print_pattern(pattern, indent)?,
+            ast::Kind::External => {
+                self.buffer.push_str("external");
+            }
+            ast::Kind::Import { .. } => {
+                self.buffer.push_str("import ");
+                self.print_import(node)?;
             }
             ast::Kind::Error => {
                 write!(self.buffer, "<Error>")?;
             }
             ast::Kind::Identifier { name } => {
-                if let Some(name) = self.interner.resolve(*name) {
-                    write!(self.buffer, "{}", name)?;
-                } else {
-                    self.buffer.push_str("<Unknown identifier>");
+                self.print_name(*name)?;
+            }
+            ast::Kind::Bool { value } => {
+                self.buffer.push_str(if *value { "True" } else { "False" });
+            }
+            ast::Kind::Text { value } => {
+                self.buffer.push('"');
+                self.print_text(*value);
+                self.buffer.push('"');
+            }
+            ast::Kind::Interpolation { parts } => {
+                self.buffer.push('"');
+                for part in parts {
+                    match self.module.get(*part).kind {
+                        ast::Kind::Text { value } => self.print_text(value),
+                        _ => {
+                            self.buffer.push('{');
+                            self.print_node(*part, indent)?;
+                            self.buffer.push('}');
+                        }
+                    }
                 }
+                self.buffer.push('"');
             }
-            ast::Kind::Number { value } => {
-                if let Some(name) = self.interner.resolve(*value) {
+            ast::Kind::Number { value, .. } => {
+                if let Some(name) = self.strings.resolve(*value) {
                     write!(self.buffer, "{}", name)?;
                 } else {
                     self.buffer.push_str("<Unknown number>");
                 }
             }
             ast::Kind::Return { expr } => {
-                self.buffer.push_str("return ");
-                self.print_node(*expr, indent)?;
+                self.buffer.push_str("return");
+                self.print_value(*expr, indent)?;
+            }
+            ast::Kind::Break { value } => {
+                self.buffer.push_str("break");
+                self.print_value(*value, indent)?;
+            }
+            ast::Kind::Continue { value } => {
+                self.buffer.push_str("continue");
+                self.print_value(*value, indent)?;
             }
         }
         Ok(())
@@ -133,9 +547,10 @@ impl<'a> Printer<'a> {
 }
 
 pub fn print(module: &Module, interner: &Interner) -> String {
+    let strings = interner.read();
     let mut printer = Printer {
         module,
-        interner,
+        strings: &strings,
         buffer: String::new(),
     };
     for node in &module.definitions {
@@ -0,0 +1,130 @@
+use std::{fmt, path::Path};
+
+use tracing::event;
+
+/// Identifies a file inside a [`SourceMap`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct FileId(u32);
+
+/// A byte range inside a file, `start` is inclusive and `end` exclusive
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(file: FileId, start: usize, end: usize) -> Self {
+        Span { file, start, end }
+    }
+
+    /// Smallest span covering both spans, they must belong to the same file
+    pub fn merge(self, other: Span) -> Span {
+        debug_assert_eq!(self.file, other.file, "Merging spans from different files");
+        Span {
+            file: self.file,
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+/// A position inside a file. Everything is zero based, the [`fmt::Display`] implementation
+/// converts to the one based `line:column` format that humans expect.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    /// Column counted in characters, what we show to the user
+    pub column: usize,
+    /// Column counted in bytes
+    pub utf8_column: usize,
+    /// Column counted in UTF-16 code units, what most editors (and the LSP by default) use
+    pub utf16_column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line + 1, self.column + 1)
+    }
+}
+
+pub struct SourceFile {
+    pub id: FileId,
+    pub name: String,
+    pub text: String,
+    /// Byte offset of the first character of every line
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    fn new(id: FileId, name: String, text: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        SourceFile {
+            id,
+            name,
+            text,
+            line_starts,
+        }
+    }
+
+    /// Converts a byte offset into a location, offsets past the end are clamped to the end of the file
+    pub fn location(&self, offset: usize) -> Location {
+        let mut offset = offset.min(self.text.len());
+        while !self.text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let prefix = &self.text[self.line_starts[line]..offset];
+        Location {
+            line,
+            column: prefix.chars().count(),
+            utf8_column: prefix.len(),
+            utf16_column: prefix.encode_utf16().count(),
+        }
+    }
+
+    /// Text of a line without the line terminator
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line];
+        let end = self.line_starts.get(line + 1).copied().unwrap_or(self.text.len());
+        self.text[start..end].trim_end_matches(['\n', '\r'])
+    }
+}
+
+/// Owns the contents of every file in a compilation session
+#[derive(Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn add(&mut self, name: impl Into<String>, text: impl Into<String>) -> FileId {
+        let id = FileId(self.files.len() as u32);
+        let file = SourceFile::new(id, name.into(), text.into());
+        event!(tracing::Level::DEBUG, "Adding file {} as {:?}", file.name, id);
+        self.files.push(file);
+        id
+    }
+
+    pub fn load(&mut self, path: impl AsRef<Path>) -> std::io::Result<FileId> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        Ok(self.add(path.display().to_string(), text))
+    }
+
+    pub fn get(&self, id: FileId) -> &SourceFile {
+        &self.files[id.0 as usize]
+    }
+
+    pub fn location(&self, span: Span) -> Location {
+        self.get(span.file).location(span.start)
+    }
+
+    /// Formats the start of a span as `file:line:column`
+    pub fn describe(&self, span: Span) -> String {
+        format!("{}:{}", self.get(span.file).name, self.location(span))
+    }
+}
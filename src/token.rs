@@ -2,21 +2,220 @@ use string_interner::{
     StringInterner,
     backend::{Backend, StringBackend},
 };
+use std::{
+    collections::VecDeque,
+    fmt,
+    sync::{PoisonError, RwLock, RwLockReadGuard},
+};
+
 use tracing::{event, span};
 
+use crate::source::{FileId, SourceFile, Span};
+
 pub type Symbol = <StringBackend as Backend>::Symbol;
-pub type Interner = StringInterner<StringBackend>;
+pub type Strings = StringInterner<StringBackend>;
+
+/// Names the compiler knows about without them being declared anywhere
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[allow(dead_code)] // For name resolution
+pub enum Builtin {
+    Int,
+    Real,
+    Bool,
+    Text,
+    Print,
+}
+
+impl Builtin {
+    const ALL: [Builtin; 5] = [Builtin::Int, Builtin::Real, Builtin::Bool, Builtin::Text, Builtin::Print];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::Int => "Int",
+            Builtin::Real => "Real",
+            Builtin::Bool => "Bool",
+            Builtin::Text => "Text",
+            Builtin::Print => "print",
+        }
+    }
+}
+
+/// Interns the strings of a whole compilation session. Every file shares it, so the same name
+/// always gets the same symbol, and it can be used from several threads to lex files in parallel.
+pub struct Interner {
+    strings: RwLock<Strings>,
+    /// Symbols of every [`Builtin`], in the same order
+    builtins: Vec<Symbol>,
+}
+
+impl Default for Interner {
+    /// An interner with the keywords and the builtin names already in it
+    fn default() -> Self {
+        let mut strings = Strings::new();
+        for &(spelling, _, _) in KEYWORDS {
+            strings.get_or_intern_static(spelling);
+        }
+        let builtins = Builtin::ALL.iter().map(|builtin| strings.get_or_intern_static(builtin.name())).collect();
+        Interner {
+            strings: RwLock::new(strings),
+            builtins,
+        }
+    }
+}
+
+impl Interner {
+    pub fn intern(&self, text: &str) -> Symbol {
+        // Most names are already interned, so try with the shared lock first
+        if let Some(symbol) = self.strings.read().unwrap_or_else(PoisonError::into_inner).get(text) {
+            return symbol;
+        }
+        self.strings.write().unwrap_or_else(PoisonError::into_inner).get_or_intern(text)
+    }
 
+    #[allow(dead_code)] // For name resolution
+    pub fn builtin(&self, builtin: Builtin) -> Symbol {
+        self.builtins[builtin as usize]
+    }
 
+    /// Locks the interner to resolve symbols, new strings can not be interned until the guard is dropped
+    pub fn read(&self) -> RwLockReadGuard<'_, Strings> {
+        self.strings.read().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+
+
+/// Identifiers follow UAX #31, with `_` also allowed as a starting character
+fn is_identifier_start(c: char) -> bool {
+    unicode_ident::is_xid_start(c) || c == '_'
+}
+
+fn is_identifier_continue(c: char) -> bool {
+    unicode_ident::is_xid_continue(c)
+}
+
+/// Whether a character can be the start of a token, comment or whitespace, used to end a run of invalid characters
+fn starts_token(c: char) -> bool {
+    c.is_whitespace() || is_identifier_start(c) || c.is_ascii_digit() || is_operator_prefix("", c) || "()[]{};\"".contains(c)
+}
+
+/// Versions of the language, matching the ones in the roadmap. Keywords introduced by an edition
+/// are plain identifiers in the previous ones, so old code keeps working when they are added.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Edition {
+    V1,
+    V2,
+    V3,
+    V4,
+    V5,
+    V6,
+    #[default]
+    V7,
+}
+
+impl std::str::FromStr for Edition {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "v1" => Ok(Edition::V1),
+            "v2" => Ok(Edition::V2),
+            "v3" => Ok(Edition::V3),
+            "v4" => Ok(Edition::V4),
+            "v5" => Ok(Edition::V5),
+            "v6" => Ok(Edition::V6),
+            "v7" => Ok(Edition::V7),
+            _ => Err(format!("Unknown edition `{}`, expected one from v1 to v7", name)),
+        }
+    }
+}
+
+/// Every keyword with its spelling and the edition that introduced it
+const KEYWORDS: &[(&str, Kind, Edition)] = &[
+    ("if", Kind::If, Edition::V1),
+    ("else", Kind::Else, Edition::V1),
+    ("loop", Kind::Loop, Edition::V1),
+    ("break", Kind::Break, Edition::V1),
+    ("continue", Kind::Continue, Edition::V1),
+    ("return", Kind::Return, Edition::V1),
+    ("external", Kind::External, Edition::V1),
+    ("and", Kind::And, Edition::V1),
+    ("or", Kind::Or, Edition::V1),
+    ("not", Kind::Not, Edition::V1),
+    ("assert", Kind::Assert, Edition::V1),
+    ("True", Kind::True, Edition::V1),
+    ("False", Kind::False, Edition::V1),
+    ("_", Kind::Underscore, Edition::V1),
+    ("import", Kind::Import, Edition::V2),
+    ("pub", Kind::Pub, Edition::V2),
+    ("match", Kind::Match, Edition::V3),
+    ("is", Kind::Is, Edition::V5),
+    ("ref", Kind::Ref, Edition::V6),
+    ("mut", Kind::Mut, Edition::V6),
+    ("effect", Kind::Effect, Edition::V7),
+    ("raise", Kind::Raise, Edition::V7),
+    ("with", Kind::With, Edition::V7),
+    ("coro", Kind::Coro, Edition::V7),
+    ("for", Kind::For, Edition::V7),
+    ("in", Kind::In, Edition::V7),
+];
+
+/// Every operator with its spelling. Every prefix of an operator must also be an operator,
+/// as they are lexed one character at a time, always taking the longest match.
+const OPERATORS: &[(&str, Kind)] = &[
+    ("+", Kind::Plus),
+    ("-", Kind::Minus),
+    ("*", Kind::Multiply),
+    ("/", Kind::Divide),
+    ("%", Kind::Modulo),
+    ("<", Kind::LessThan),
+    (">", Kind::GreaterThan),
+    ("=", Kind::Equal),
+    ("<=", Kind::LessThanOrEqual),
+    (">=", Kind::GreaterThanOrEqual),
+    ("==", Kind::EqualEqual),
+    ("!=", Kind::NotEqual),
+    ("<>", Kind::LessGreater),
+    ("+=", Kind::PlusEqual),
+    ("-=", Kind::MinusEqual),
+    ("*=", Kind::MultiplyEqual),
+    ("/=", Kind::DivideEqual),
+    (":", Kind::Colon),
+    (":=", Kind::ColonEqual),
+    ("::", Kind::ColonColon),
+    ("->", Kind::Arrow),
+    (",", Kind::Comma),
+    (".", Kind::Dot),
+    ("..", Kind::DotDot),
+    ("...", Kind::Ellipsis),
+    ("|", Kind::Pipe),
+    ("!", Kind::Bang),
+];
+
+/// Whether adding the character to the already consumed operator still gives an operator
+fn is_operator_prefix(operator: &str, c: char) -> bool {
+    OPERATORS.iter().any(|(spelling, _)| {
+        spelling.len() == operator.len() + c.len_utf8() && spelling.starts_with(operator) && spelling.ends_with(c)
+    })
+}
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum Kind {
-    Number(Symbol),
+    /// The interned text as written in the source and its value
+    Number(Symbol, Literal),
+    /// Text literal with the escapes already processed
     Text(Symbol),
+    /// Interpolated text literals are split around the interpolated expressions, the tokens
+    /// of each expression come between these parts: `"a {x} b {y} c"` becomes
+    /// `TextStart(a) x TextMiddle(b) y TextEnd(c)`
+    TextStart(Symbol),
+    TextMiddle(Symbol),
+    TextEnd(Symbol),
     Plus,
     Minus,
     Multiply,
     Divide,
+    Modulo,
     LessThan,
     GreaterThan,
     Equal,
@@ -24,6 +223,13 @@ pub enum Kind {
     LessThanOrEqual,
     GreaterThanOrEqual,
     NotEqual,
+    /// `<>`, another way of writing `!=`
+    LessGreater,
+    PlusEqual,
+    MinusEqual,
+    MultiplyEqual,
+    DivideEqual,
+    Colon,
     ColonEqual,
     ColonColon,
     Arrow,
@@ -32,6 +238,10 @@ pub enum Kind {
     Not,
     Comma,
     Dot,
+    DotDot,
+    Ellipsis,
+    Pipe,
+    Bang,
     // Delimiters
     LParen,
     RParen,
@@ -50,15 +260,171 @@ pub enum Kind {
     Loop,
     Import,
     External,
+    Match,
+    With,
+    Raise,
+    /// Only in front of a signature, `effect (Int) -> Int`, anywhere else `effect` is an identifier
+    Effect,
+    Pub,
+    Ref,
+    Mut,
+    Is,
+    Assert,
+    True,
+    False,
+    Coro,
+    /// A lone `_`, the wildcard of patterns. Longer names like `_private` are identifiers
+    Underscore,
     // Fallbacks
     Identifier(Symbol),
     Unknown(Symbol),
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Literal {
+    Int(u64),
+    /// Stored as bits so tokens can still be compared, use [`f64::from_bits`] to get the value back
+    Real(u64),
+}
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Token {
     pub kind: Kind,
-    pub span: (usize, usize),
+    pub span: Span,
+    /// Whether there is a line break between this token and the previous one, the parser uses
+    /// it to know where statements end
+    pub newline_before: bool,
+    /// Range of [`Lexed::trivia`] before the token, only filled when lexing with trivia
+    pub leading: (usize, usize),
+    /// Range of [`Lexed::trivia`] after the token up to the end of its line, only filled when lexing with trivia
+    pub trailing: (usize, usize),
+}
+
+impl Token {
+    fn new(kind: Kind, span: Span) -> Self {
+        Token {
+            kind,
+            span,
+            newline_before: false,
+            leading: (0, 0),
+            trailing: (0, 0),
+        }
+    }
+}
+
+/// Everything in the source that is not a token
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TriviaKind {
+    Whitespace,
+    Newline,
+    LineComment,
+    /// Block comments starting with `;;;`, like `;;; Adds two numbers ;;`
+    DocComment,
+    BlockComment,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Trivia {
+    pub kind: TriviaKind,
+    pub span: Span,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Options {
+    pub edition: Edition,
+    /// Keep comments and whitespace as trivia, so the source can be reproduced byte by byte
+    pub trivia: bool,
+}
+
+pub struct Lexed {
+    pub tokens: Vec<Token>,
+    /// Empty unless [`Options::trivia`] is set
+    pub trivia: Vec<Trivia>,
+    pub errors: Vec<Error>,
+}
+
+impl Lexed {
+    /// Rebuilds the source from the tokens and their trivia, it is only lossless when lexing with trivia
+    #[allow(dead_code)] // For the formatter and the LSP
+    pub fn reconstruct(&self, source: &str) -> String {
+        let text = |span: Span| &source[span.start..span.end];
+        let mut result = String::with_capacity(source.len());
+        if self.tokens.is_empty() {
+            self.trivia.iter().for_each(|trivia| result.push_str(text(trivia.span)));
+        }
+        for token in &self.tokens {
+            for trivia in &self.trivia[token.leading.0..token.leading.1] {
+                result.push_str(text(trivia.span));
+            }
+            result.push_str(text(token.span));
+            for trivia in &self.trivia[token.trailing.0..token.trailing.1] {
+                result.push_str(text(trivia.span));
+            }
+        }
+        result
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorKind {
+    UnterminatedText,
+    InvalidEscape(char),
+    /// A `\u{...}` escape that is not a valid unicode scalar value
+    InvalidUnicodeEscape,
+    UnterminatedBlockComment,
+    InvalidCharacter(char),
+    Number(NumberError),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NumberError {
+    /// Characters that are not digits of the literal's base, or a prefix with no digits after it
+    Malformed,
+    LeadingUnderscore,
+    TrailingUnderscore,
+    /// A leading `0` is reserved for the `0x`, `0o` and `0b` prefixes
+    LeadingZero,
+    /// The literal does not fit in 64 bits
+    Overflow,
+    /// The literal can not be represented exactly with a 64 bit float
+    PrecisionLoss,
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::UnterminatedText => write!(f, "unterminated text literal"),
+            ErrorKind::InvalidEscape(c) => write!(f, "unknown escape sequence `\\{}`", c.escape_debug()),
+            ErrorKind::InvalidUnicodeEscape => write!(f, "invalid unicode escape, expected `\\u{{...}}` with 1 to 6 hex digits"),
+            ErrorKind::UnterminatedBlockComment => write!(f, "unterminated block comment"),
+            ErrorKind::InvalidCharacter(c) => write!(f, "invalid character `{}`", c.escape_debug()),
+            ErrorKind::Number(NumberError::Malformed) => write!(f, "malformed number literal"),
+            ErrorKind::Number(NumberError::LeadingUnderscore) => write!(f, "number literals can not start with an underscore"),
+            ErrorKind::Number(NumberError::TrailingUnderscore) => write!(f, "number literals can not end with an underscore"),
+            ErrorKind::Number(NumberError::LeadingZero) => {
+                write!(f, "leading zeros are reserved for the `0x`, `0o` and `0b` prefixes")
+            }
+            ErrorKind::Number(NumberError::Overflow) => write!(f, "number literal is too big, the maximum is {}", u64::MAX),
+            ErrorKind::Number(NumberError::PrecisionLoss) => {
+                write!(f, "real literal has more precision than what can be represented")
+            }
+        }
+    }
+}
+
+/// A fix for an error, replacing the text in `span` with `replacement`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Suggestion {
+    pub message: &'static str,
+    pub span: Span,
+    pub replacement: &'static str,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub span: Span,
+    pub suggestion: Option<Suggestion>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
@@ -68,79 +434,267 @@ enum ParserState {
     InDecimal(usize),
     InIdentifier(usize),
     InOperator(usize),
+    /// Starts at the opening `"`, or at the `}` that closes an interpolation
     InText(usize),
+    /// Raw text like `r#"..."#`, the second field is the number of `#`
+    InRawText(usize, usize),
     InDelimiter(usize),
     InComment(usize),
+    /// Block comments are delimited by `;;` on both sides, so the only way to tell an opening
+    /// `;;` from a closing one is what surrounds it: a `;;` after whitespace or the start of the
+    /// input that is followed by the start of a word, like in `;; nested ;;` or `;;nested`,
+    /// opens a nested comment. Anything else closes the innermost one, so `f(1 ;; note ;;)` works.
+    /// The first field is the start of the outermost `;;`, the second one the nesting depth.
+    InBlockComment(usize, usize),
     InUnknown(usize),
     Eof,    
 }
 
-struct Parser<'a> {
+/// Turns text into tokens one at a time, so the whole input never has to be buffered as tokens.
+/// Errors and trivia are collected on the side and can be taken with [`Lexer::finish`].
+pub struct Lexer<'a> {
     input: &'a str,
+    file: FileId,
+    options: Options,
     pos: usize,
-    interner: Interner,
+    interner: &'a Interner,
     state: ParserState,
-    tokens: Vec<Token>,
+    /// Tokens that are complete but were not handed out yet
+    tokens: VecDeque<Token>,
+    /// With trivia the last token is held back until we know where its trailing trivia ends
+    held: Option<Token>,
+    /// Kind of the last token created
+    previous: Option<Kind>,
+    errors: Vec<Error>,
+    trivia: Vec<Trivia>,
+    /// Whether we consumed a line break since the last token
+    newline: bool,
+    /// For every text literal we are interpolating into: where the literal started and how many braces are open
+    interpolations: Vec<(usize, usize)>,
 }
 
-impl<'a> Parser<'a> {
-    pub fn new(input: &'a str) -> Self {
-        Parser {
+impl<'a> Lexer<'a> {
+    pub fn new(input: &'a str, file: FileId, options: Options, interner: &'a Interner) -> Self {
+        Lexer {
             input,
+            file,
+            options,
             pos: 0,
-            interner: StringInterner::default(),
+            interner,
             state: ParserState::Start,
-            tokens: Vec::new(),
+            tokens: VecDeque::new(),
+            held: None,
+            previous: None,
+            errors: Vec::new(),
+            trivia: Vec::new(),
+            newline: false,
+            interpolations: Vec::new(),
         }
     }
 
+    /// Errors and trivia found so far, all of them once the lexer returned `None`
+    pub fn finish(self) -> (Vec<Error>, Vec<Trivia>) {
+        (self.errors, self.trivia)
+    }
+
     fn peek(&self) -> Option<char> {
         self.input[self.pos..].chars().next()
     }
 
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.input[self.pos..].chars().nth(n)
+    }
+
+    fn push_trivia(&mut self, kind: TriviaKind, start: usize) {
+        if !self.options.trivia {
+            return;
+        }
+        match self.trivia.last_mut() {
+            // Runs of spaces and tabs are a single piece of trivia
+            Some(last) if kind == TriviaKind::Whitespace && last.kind == kind && last.span.end == start => {
+                last.span.end = self.pos;
+            }
+            _ => self.trivia.push(Trivia {
+                kind,
+                span: self.span(start),
+            }),
+        }
+    }
+
+    /// Gives the held token its trailing trivia and holds the new one instead. Trailing trivia goes up to
+    /// the end of the line, everything after that is leading trivia of the next token.
+    /// The last token takes the rest of the file.
+    fn attach_trivia(&mut self, token: Option<Token>) {
+        let end = self.trivia.len();
+        let mut leading_start = 0;
+        if let Some(mut held) = self.held.take() {
+            let trailing_start = held.trailing.0;
+            leading_start = match token {
+                Some(_) => (trailing_start..end).find(|&i| self.trivia[i].kind == TriviaKind::Newline).unwrap_or(end),
+                None => end,
+            };
+            held.trailing = (trailing_start, leading_start);
+            self.tokens.push_back(held);
+        }
+        self.held = token.map(|token| Token {
+            leading: (leading_start, end),
+            trailing: (end, end),
+            ..token
+        });
+    }
+
+    /// `effect` is only a keyword in front of a signature, so `effect (Int) -> Int` declares an effect
+    /// while `raise effect` uses a value named `effect`. It is decided here with the token after it,
+    /// so the parser and everything else that reads the tokens agree on it.
+    fn resolve_contextual_keyword(&mut self, token: Token) -> Token {
+        if token.kind != Kind::Effect {
+            return token;
+        }
+        // With trivia the next token is the held one
+        while self.tokens.is_empty() && self.held.is_none() && self.state != ParserState::Eof {
+            self.step();
+        }
+        let next = self.tokens.front().or(self.held.as_ref()).map(|next| next.kind);
+        if matches!(next, Some(Kind::LParen | Kind::LessThan)) {
+            token
+        } else {
+            Token {
+                kind: Kind::Identifier(self.interner.intern("effect")),
+                ..token
+            }
+        }
+    }
+
+    fn end_line_comment(&mut self, start: usize) {
+        self.push_trivia(TriviaKind::LineComment, start);
+        self.state = ParserState::Start;
+    }
+
+    fn push_block_comment(&mut self, start: usize) {
+        let kind = if self.input[start..].starts_with(";;;") { TriviaKind::DocComment } else { TriviaKind::BlockComment };
+        self.push_trivia(kind, start);
+    }
+
+    fn unterminated_text(&mut self, start: usize) {
+        let suggestion = Suggestion {
+            message: "close the text literal",
+            span: Span::new(self.file, self.pos, self.pos),
+            replacement: "\"",
+        };
+        // Point at the opening quote, the end of the file is not very helpful
+        self.error(ErrorKind::UnterminatedText, Span::new(self.file, start, start + 1), Some(suggestion));
+    }
+
+    /// Interns the content of a text literal, processing the escape sequences
+    fn intern_text(&mut self, start: usize, end: usize) -> Symbol {
+        let raw = &self.input[start..end];
+        if !raw.contains('\\') {
+            return self.interner.intern(raw);
+        }
+        let mut text = String::with_capacity(raw.len());
+        let mut chars = raw.char_indices().peekable();
+        while let Some((i, c)) = chars.next() {
+            if c != '\\' {
+                text.push(c);
+                continue;
+            }
+            let escape_start = start + i;
+            let escaped = match chars.next() {
+                Some((_, 'n')) => '\n',
+                Some((_, 't')) => '\t',
+                Some((_, 'r')) => '\r',
+                Some((_, '0')) => '\0',
+                Some((_, c @ ('"' | '\\' | '{' | '}'))) => c,
+                Some((_, 'u')) => {
+                    let mut hex = String::new();
+                    let mut closed = false;
+                    if chars.next_if(|&(_, c)| c == '{').is_some() {
+                        while let Some((_, c)) = chars.next_if(|&(_, c)| c.is_ascii_hexdigit() || c == '}') {
+                            if c == '}' {
+                                closed = true;
+                                break;
+                            }
+                            hex.push(c);
+                        }
+                    }
+                    let escape_end = chars.peek().map_or(end, |&(i, _)| start + i);
+                    let value = u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32);
+                    match value {
+                        Some(c) if closed && hex.len() <= 6 => c,
+                        _ => {
+                            let span = Span::new(self.file, escape_start, escape_end);
+                            self.error(ErrorKind::InvalidUnicodeEscape, span, None);
+                            char::REPLACEMENT_CHARACTER
+                        }
+                    }
+                }
+                Some((j, c)) => {
+                    let span = Span::new(self.file, escape_start, start + j + c.len_utf8());
+                    let suggestion = Suggestion {
+                        message: "if you meant a backslash, escape it",
+                        span: Span::new(self.file, escape_start, escape_start + 1),
+                        replacement: "\\\\",
+                    };
+                    self.error(ErrorKind::InvalidEscape(c), span, Some(suggestion));
+                    c
+                }
+                None => '\\', // Only happens in unterminated literals, that already have an error
+            };
+            text.push(escaped);
+        }
+        self.interner.intern(&text)
+    }
+
+    /// Number of `#` in a raw text opening like `r##"`, if that is what we are looking at
+    fn raw_text_hashes(&self) -> Option<usize> {
+        let rest = self.input[self.pos..].strip_prefix('r')?;
+        let hashes = rest.len() - rest.trim_start_matches('#').len();
+        rest[hashes..].starts_with('"').then_some(hashes)
+    }
+
+    /// Whether the `_` we are looking at is the start of something like `_12` or `_.5`
+    fn underscore_starts_number(&self) -> bool {
+        let mut rest = self.input[self.pos..].trim_start_matches('_').chars();
+        match rest.next() {
+            Some('.') => rest.next().is_some_and(|c| c.is_ascii_digit()),
+            Some(c) => c.is_ascii_digit(),
+            None => false,
+        }
+    }
+
+    fn span(&self, start: usize) -> Span {
+        Span::new(self.file, start, self.pos)
+    }
+
+    fn error(&mut self, kind: ErrorKind, span: Span, suggestion: Option<Suggestion>) {
+        let error = Error { kind, span, suggestion };
+        event!(tracing::Level::DEBUG, "Lexer error: {:?}", error);
+        self.errors.push(error);
+    }
+
     fn create_token(&mut self) {
         span!(tracing::Level::DEBUG, "create_token", state = ?self.state);
         let token = match self.state {            
             ParserState::InIdentifier(start) => {
-                match &self.input[start..self.pos] {
-                    "if" => Token { kind: Kind::If, span: (start, self.pos) },
-                    "else" => Token { kind: Kind::Else, span: (start, self.pos) },
-                    "for" => Token { kind: Kind::For, span: (start, self.pos) },
-                    "in" => Token { kind: Kind::In, span: (start, self.pos) },
-                    "import" => Token { kind: Kind::Import, span: (start, self.pos) },
-                    "and" => Token { kind: Kind::And, span: (start, self.pos) },
-                    "or" => Token { kind: Kind::Or, span: (start, self.pos) },
-                    "not" => Token { kind: Kind::Not, span: (start, self.pos) },
-                    "return" => Token { kind: Kind::Return, span: (start, self.pos) },
-                    "break" => Token { kind: Kind::Break, span: (start, self.pos) },
-                    "continue" => Token { kind: Kind::Continue, span: (start, self.pos) },
-                    "loop" => Token { kind: Kind::Loop, span: (start, self.pos) },
-                    "external" => Token { kind: Kind::External, span: (start, self.pos) },
-                    consumed => Token { kind: Kind::Identifier(self.interner.get_or_intern(consumed)), span: (start, self.pos) },
-                }
+                let consumed = &self.input[start..self.pos];
+                // Keywords can still be used as names after a dot, so `read_file.coro` works
+                let after_dot = self.previous == Some(Kind::Dot);
+                let keyword = KEYWORDS
+                    .iter()
+                    .find(|&&(spelling, _, since)| spelling == consumed && since <= self.options.edition && !after_dot);
+                let kind = match keyword {
+                    Some(&(_, kind, _)) => kind,
+                    None => Kind::Identifier(self.interner.intern(consumed)),
+                };
+                Token::new(kind, self.span(start))
             }
             ParserState::InOperator(start) => {
                 let operator = &self.input[start..self.pos];
-                let kind = match operator {
-                    "+" => Kind::Plus,
-                    "-" => Kind::Minus,
-                    "*" => Kind::Multiply,
-                    "/" => Kind::Divide,
-                    "<" => Kind::LessThan,
-                    ">" => Kind::GreaterThan,
-                    "=" => Kind::Equal,
-                    "<=" => Kind::LessThanOrEqual,
-                    ">=" => Kind::GreaterThanOrEqual,
-                    "==" => Kind::EqualEqual,
-                    "!=" => Kind::NotEqual,
-                    ":=" => Kind::ColonEqual,
-                    "::" => Kind::ColonColon,
-                    "->" => Kind::Arrow,
-                    "," => Kind::Comma,
-                    "." => Kind::Dot,
-                    _ => Kind::Unknown(self.interner.get_or_intern(operator)),
+                let kind = match OPERATORS.iter().find(|(spelling, _)| *spelling == operator) {
+                    Some(&(_, kind)) => kind,
+                    None => unreachable!("Operators are only consumed while they match the table: {}", operator),
                 };
-                Token { kind, span: (start, self.pos) }
+                Token::new(kind, self.span(start))
             }
             ParserState::InDelimiter(start) => {
                 let delimiter = &self.input[start..self.pos];
@@ -151,48 +705,151 @@ impl<'a> Parser<'a> {
                     "}" => Kind::RBrace,
                     "[" => Kind::LBracket,
                     "]" => Kind::RBracket,
-                    _ => Kind::Unknown(self.interner.get_or_intern(delimiter)),
+                    _ => Kind::Unknown(self.interner.intern(delimiter)),
                 };
-                Token { kind, span: (start, self.pos) }
+                Token::new(kind, self.span(start))
             }
             ParserState::InNumber(start) | ParserState::InDecimal(start) => {
-                Token {
-                    kind: Kind::Number(self.interner.get_or_intern(&self.input[start..self.pos])),
-                    span: (start, self.pos),
+                let number = &self.input[start..self.pos];
+                let (literal, problem) = parse_number(number);
+                if let Some((error, (from, to))) = problem {
+                    let span = Span::new(self.file, start + from, start + to);
+                    let suggestion = match error {
+                        NumberError::LeadingZero => Some("remove the leading zeros"),
+                        NumberError::LeadingUnderscore | NumberError::TrailingUnderscore => Some("remove the underscore"),
+                        NumberError::Malformed | NumberError::Overflow | NumberError::PrecisionLoss => None,
+                    };
+                    let suggestion = suggestion.map(|message| Suggestion { message, span, replacement: "" });
+                    self.error(ErrorKind::Number(error), span, suggestion);
                 }
+                Token::new(Kind::Number(self.interner.intern(number), literal), self.span(start))
             }
             ParserState::InText(start) => {
-                Token {
-                    kind: Kind::Text(self.interner.get_or_intern(&self.input[start..self.pos])),
-                    span: (start, self.pos),
+                let text = &self.input[start..self.pos];
+                let continues = text.starts_with('}');
+                let interpolates = text.len() > 1 && ends_with_unescaped(text, '{');
+                let content_end = if text.len() > 1 && (interpolates || ends_with_unescaped(text, '"')) {
+                    self.pos - 1
+                } else {
+                    self.pos // Unterminated
+                };
+                let symbol = self.intern_text(start + 1, content_end);
+                let kind = match (continues, interpolates) {
+                    (false, false) => Kind::Text(symbol),
+                    (false, true) => Kind::TextStart(symbol),
+                    (true, true) => Kind::TextMiddle(symbol),
+                    (true, false) => Kind::TextEnd(symbol),
+                };
+                if interpolates {
+                    let literal_start = match self.interpolations.last() {
+                        Some(&(literal_start, _)) if continues => literal_start,
+                        _ => start,
+                    };
+                    if continues {
+                        self.interpolations.pop();
+                    }
+                    self.interpolations.push((literal_start, 0));
+                } else if continues {
+                    self.interpolations.pop();
                 }
+                Token::new(kind, self.span(start))
+            }
+            ParserState::InRawText(start, hashes) => {
+                // `r`, the hashes and the quote on the left, the quote and the hashes on the right
+                let text = &self.input[start..self.pos];
+                let closing = text.len() - text.trim_end_matches('#').len() >= hashes && text[..text.len() - hashes].ends_with('"');
+                let terminated = text.len() >= 2 * hashes + 3 && closing;
+                let content_end = if terminated { self.pos - hashes - 1 } else { self.pos };
+                let content = &self.input[start + hashes + 2..content_end];
+                Token::new(Kind::Text(self.interner.intern(content)), self.span(start))
             }
             ParserState::InUnknown(start) => {
-                Token {
-                    kind: Kind::Unknown(self.interner.get_or_intern(&self.input[start..self.pos])),
-                    span: (start, self.pos),
-                }
+                let unknown = &self.input[start..self.pos];
+                let first = unknown.chars().next().expect("Unknown tokens are never empty");
+                let suggestion = match first {
+                    '“' | '”' if unknown.chars().count() == 1 => Suggestion {
+                        message: "use a plain double quote",
+                        span: self.span(start),
+                        replacement: "\"",
+                    },
+                    _ => Suggestion {
+                        message: "remove it",
+                        span: self.span(start),
+                        replacement: "",
+                    },
+                };
+                self.error(ErrorKind::InvalidCharacter(first), self.span(start), Some(suggestion));
+                Token::new(Kind::Unknown(self.interner.intern(unknown)), self.span(start))
             }
-            ParserState::InComment(_) | ParserState::Eof | ParserState::Start => {
+            ParserState::InComment(_)
+            | ParserState::InBlockComment(..)
+            | ParserState::Eof
+            | ParserState::Start => {
                 unreachable!("Unexpected parser state when creating token: {:?}", self.state)
             }            
         };
+        let token = Token {
+            newline_before: std::mem::take(&mut self.newline),
+            ..token
+        };
         event!(tracing::Level::DEBUG, "Creating token: {:?}", token);
-        self.tokens.push(token);
+        self.previous = Some(token.kind);
+        if self.options.trivia {
+            self.attach_trivia(Some(token));
+        } else {
+            self.tokens.push_back(token);
+        }
         self.state = ParserState::Start; // Reset state after creating token
     }
 
     fn consume(&mut self) -> usize {
         event!(tracing::Level::DEBUG, "Consuming character {}",  self.input[self.pos..].chars().next().unwrap_or(' '));
         let start = self.pos;
-        self.pos += 1;
+        let c = self.peek();
+        // Line breaks inside text literals are part of the text, not a separator
+        if c == Some('\n') && !matches!(self.state, ParserState::InText(_) | ParserState::InRawText(..)) {
+            self.newline = true;
+        }
+        self.pos += c.map_or(0, char::len_utf8);
         start
     }
 
-    fn next(&mut self) {
+    fn step(&mut self) {
         let Some(c) = self.peek() else {
-            if self.state != ParserState::Eof || self.state != ParserState::Start {
-                self.create_token(); // Create token for the last state
+            match self.state {
+                ParserState::Start if !self.interpolations.is_empty() => {
+                    // The file ended inside of an interpolated expression
+                    let (start, _) = self.interpolations[self.interpolations.len() - 1];
+                    self.unterminated_text(start);
+                }
+                ParserState::InText(start) | ParserState::InRawText(start, _) => {
+                    let start = match self.interpolations.last() {
+                        Some(&(literal_start, _)) if self.input[start..].starts_with('}') => literal_start,
+                        _ => start,
+                    };
+                    self.unterminated_text(start);
+                    self.create_token();
+                }
+                ParserState::InComment(start) => self.end_line_comment(start),
+                ParserState::Start | ParserState::Eof => {}
+                ParserState::InBlockComment(start, _) => {
+                    self.push_block_comment(start);
+                    // Point at the outermost `;;`, that is the one that is missing its pair
+                    let suggestion = Suggestion {
+                        message: "close the comment at the end of the file",
+                        span: Span::new(self.file, self.pos, self.pos),
+                        replacement: " ;;",
+                    };
+                    self.error(
+                        ErrorKind::UnterminatedBlockComment,
+                        Span::new(self.file, start, start + 2),
+                        Some(suggestion),
+                    );
+                }
+                _ => self.create_token(), // Create token for the last state
+            }
+            if self.options.trivia {
+                self.attach_trivia(None);
             }
             self.state = ParserState::Eof;
             return;
@@ -203,54 +860,125 @@ impl<'a> Parser<'a> {
 
         match (self.state, c) {
             (ParserState::Start, _) if c.is_whitespace() => {
-                self.consume(); // Skip whitespace
+                let start = self.consume(); // Skip whitespace
+                let kind = if c == '\n' { TriviaKind::Newline } else { TriviaKind::Whitespace };
+                self.push_trivia(kind, start);
+            }
+            (ParserState::Start, '_') if self.underscore_starts_number() => {
+                // Not a valid number, but it reads like one, so it is better reported as such
+                self.state = ParserState::InNumber(self.consume());
             }
-            (ParserState::Start, _) if c.is_alphabetic() || c == '_' => {
+            (ParserState::Start, 'r') if self.raw_text_hashes().is_some() => {
+                let hashes = self.raw_text_hashes().unwrap_or_default();
+                let start = self.consume();
+                for _ in 0..hashes + 1 {
+                    self.consume();
+                }
+                self.state = ParserState::InRawText(start, hashes);
+            }
+            (ParserState::Start, _) if is_identifier_start(c) => {
                 self.state = ParserState::InIdentifier(self.consume());
             }
-            (ParserState::Start, _) if c.is_numeric() => {
+            (ParserState::Start, _) if c.is_ascii_digit() => {
                 self.state = ParserState::InNumber(self.consume());
             }
-            (ParserState::Start, _) if "+-*/<>:=,.".contains(c) => {
+            (ParserState::Start, _) if is_operator_prefix("", c) => {
                 self.state = ParserState::InOperator(self.consume());
             }
+            (ParserState::Start, '}') if self.interpolations.last().is_some_and(|&(_, depth)| depth == 0) => {
+                // End of the interpolated expression, back to the text
+                self.state = ParserState::InText(self.consume());
+            }
             (ParserState::Start, _) if "()[]{}".contains(c) => {
+                if let Some((_, depth)) = self.interpolations.last_mut() {
+                    match c {
+                        '{' => *depth += 1,
+                        '}' => *depth -= 1,
+                        _ => {}
+                    }
+                }
                 self.state = ParserState::InDelimiter(self.consume());
             }
             (ParserState::Start, '"') => {
                 self.state = ParserState::InText(self.consume());
             }
+            (ParserState::Start, ';') if self.peek_nth(1) == Some(';') => {
+                let start = self.consume();
+                self.consume();
+                self.state = ParserState::InBlockComment(start, 1);
+            }
             (ParserState::Start, ';') => {
                 self.state = ParserState::InComment(self.consume());
-            }            
+            }
             (ParserState::Start, _) => {
                 self.state = ParserState::InUnknown(self.consume());
             }    
-            (ParserState::InUnknown(_), _) if c.is_alphanumeric() || "+-*/<>:=,._()[]{};\"".contains(c) => {
+            (ParserState::InUnknown(_), _) if starts_token(c) => {
                 self.create_token();
             }
-            (ParserState::InIdentifier(_), _) if !c.is_alphanumeric() && c != '_' => {
+            (ParserState::InIdentifier(_), _) if !is_identifier_continue(c) => {
                 self.create_token();
             }
-            (ParserState::InNumber(start), '.') => {
+            (ParserState::InNumber(start), '.') if self.peek_nth(1).is_some_and(|c| c.is_ascii_digit() || c == '_') => {
                 self.consume(); // Consume the dot for decimal
                 self.state = ParserState::InDecimal(start);
             }
-            (ParserState::InNumber(_) | ParserState::InDecimal(_), _) if !c.is_numeric() && c != '_'  => {
+            // Letters glued to a number are kept in it so we can report the whole thing as malformed
+            (ParserState::InNumber(_) | ParserState::InDecimal(_), _) if !is_identifier_continue(c) => {
                 self.create_token();
             }
-            (ParserState::InOperator(_), _) if !"+-*/<>:=,.".contains(c) => {
+            // Maximal munch, the operator keeps growing while there is a longer one in the table
+            (ParserState::InOperator(start), _) if !is_operator_prefix(&self.input[start..self.pos], c) => {
                 self.create_token();
             }
             (ParserState::InDelimiter(_), _) => {
                 self.create_token();
             }
-            (ParserState::InText(_), '"') => {
-                self.consume(); // Consume closing quote
+            (ParserState::InText(_), '\\') => {
+                self.consume(); // Consume the backslash and whatever it escapes
+                let escaped = self.peek();
+                self.consume();
+                if escaped == Some('u') && self.peek() == Some('{') {
+                    // The braces of unicode escapes do not start an interpolation
+                    self.consume();
+                    while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+                        self.consume();
+                    }
+                    if self.peek() == Some('}') {
+                        self.consume();
+                    }
+                }
+            }
+            (ParserState::InText(_), '"' | '{') => {
+                self.consume(); // Consume closing quote or the opening brace of an interpolation
                 self.create_token();
             }
-            (ParserState::InComment(_), '\n') => {
-                self.consume(); // Consume newline to end comment
+            (ParserState::InRawText(_, hashes), '"') if self.input[self.pos + 1..].bytes().take_while(|&b| b == b'#').count() >= hashes => {
+                for _ in 0..hashes + 1 {
+                    self.consume();
+                }
+                self.create_token();
+            }
+            (ParserState::InComment(start), '\n') => {
+                // The newline is not part of the comment, it is consumed as whitespace
+                self.end_line_comment(start);
+            }
+            (ParserState::InBlockComment(start, depth), ';') if self.peek_nth(1) == Some(';') => {
+                let after_space = self.input[..self.pos].chars().next_back().is_none_or(char::is_whitespace);
+                self.consume();
+                self.consume();
+                let word_follows = self.peek().is_some_and(|c| {
+                    !c.is_whitespace() && !matches!(c, ';' | ')' | ']' | '}') && !is_operator_prefix("", c)
+                });
+                let closes = !(after_space && word_follows);
+                self.state = match (closes, depth) {
+                    (true, 1) => {
+                        self.push_block_comment(start);
+                        ParserState::Start
+                    }
+                    (true, _) => ParserState::InBlockComment(start, depth - 1),
+                    (false, _) => ParserState::InBlockComment(start, depth + 1),
+                };
             }
             _ => {
                 // Continue consuming characters in the current state
@@ -262,14 +990,422 @@ impl<'a> Parser<'a> {
     // Additional methods for parsing would go here
 }
 
+impl Iterator for Lexer<'_> {
+    type Item = Token;
 
-pub fn parse(input: &str) -> (Vec<Token>, Interner) {
-    event!(tracing::Level::DEBUG, "Starting parsing input: {}", input);
-    let mut parser = Parser::new(input);
+    fn next(&mut self) -> Option<Token> {
+        loop {
+            if let Some(token) = self.tokens.pop_front() {
+                return Some(self.resolve_contextual_keyword(token));
+            }
+            if self.state == ParserState::Eof {
+                return None;
+            }
+            self.step();
+        }
+    }
+}
+
+/// Whether the text ends with the character and it is not escaped by an odd number of backslashes before it
+fn ends_with_unescaped(text: &str, c: char) -> bool {
+    let Some(rest) = text.strip_suffix(c) else {
+        return false;
+    };
+    let backslashes = rest.len() - rest.trim_end_matches('\\').len();
+    backslashes % 2 == 0
+}
 
-    while parser.state != ParserState::Eof {
-        parser.next();
+type NumberProblem = (NumberError, (usize, usize));
+
+/// Validates a number literal and computes its value. Problems are reported together with the
+/// byte range of the literal that caused them, but a best effort value is always returned.
+fn parse_number(text: &str) -> (Literal, Option<NumberProblem>) {
+    let whole = (0, text.len());
+    let (radix, prefix) = match text.get(..2) {
+        Some("0x") => (16, 2),
+        Some("0o") => (8, 2),
+        Some("0b") => (2, 2),
+        _ => (10, 0),
+    };
+    let digits = &text[prefix..];
+    let (integer, fraction) = match digits.split_once('.') {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (digits, None),
+    };
+    let clean = |part: &str| part.chars().filter(|&c| c != '_').collect::<String>();
+
+    let mut problem = None;
+    let mut report = |error, range| {
+        problem.get_or_insert((error, range));
+    };
+    let fraction_start = prefix + integer.len() + 1;
+    for (part, offset, is_fraction) in [(Some(integer), prefix, false), (fraction, fraction_start, true)] {
+        let Some(part) = part else { continue };
+        let leading = part.len() - part.trim_start_matches('_').len();
+        let trailing = part.len() - part.trim_end_matches('_').len();
+        if part.is_empty() {
+            report(NumberError::Malformed, whole);
+        } else if leading == part.len() {
+            // Only underscores, like in `_.5` or `1._`, what side of the dot they are on tells which one it is
+            let error = if is_fraction { NumberError::TrailingUnderscore } else { NumberError::LeadingUnderscore };
+            report(error, (offset, offset + part.len()));
+        } else if leading > 0 {
+            report(NumberError::LeadingUnderscore, (offset, offset + leading));
+        } else if trailing > 0 {
+            report(NumberError::TrailingUnderscore, (offset + part.len() - trailing, offset + part.len()));
+        }
+    }
+    let valid_digits = digits.chars().all(|c| c == '_' || c == '.' || c.is_digit(radix));
+    if !valid_digits || (radix != 10 && fraction.is_some()) {
+        report(NumberError::Malformed, whole);
+    }
+    let integer_digits = clean(integer);
+    if radix == 10 && integer_digits.len() > 1 && integer_digits.starts_with('0') {
+        let zeros = integer.len() - integer.trim_start_matches(['0', '_']).len();
+        // Keep the last zero if the literal is only zeros
+        let zeros = if zeros == integer.len() { zeros - 1 } else { zeros };
+        report(NumberError::LeadingZero, (0, zeros));
     }
 
-    (parser.tokens, parser.interner)
+    let literal = match fraction {
+        None => {
+            let value = if valid_digits {
+                u64::from_str_radix(&integer_digits, radix).ok()
+            } else {
+                Some(0)
+            };
+            if value.is_none() && !integer_digits.is_empty() {
+                report(NumberError::Overflow, whole);
+            }
+            Literal::Int(value.unwrap_or(u64::MAX))
+        }
+        Some(fraction) => {
+            let written = format!("{}.{}", integer_digits, clean(fraction));
+            let value: f64 = written.parse().unwrap_or(0.0);
+            // The shortest representation of a float that parses back to it is what Display gives us,
+            // if it is not the same number as the one written some of the digits were lost
+            if valid_digits && normalize_real(&written) != normalize_real(&value.to_string()) {
+                report(NumberError::PrecisionLoss, whole);
+            }
+            Literal::Real(value.to_bits())
+        }
+    };
+    (literal, problem)
+}
+
+/// Strips the zeros that do not change the value of a real number written in decimal
+fn normalize_real(text: &str) -> String {
+    let (integer, fraction) = text.split_once('.').unwrap_or((text, ""));
+    let integer = integer.trim_start_matches('0');
+    let fraction = fraction.trim_end_matches('0');
+    format!("{}.{}", integer, fraction)
+}
+
+/// A change to the text of a file, the bytes from `start` to `end` are replaced by `text`
+#[derive(Copy, Clone, Debug)]
+#[allow(dead_code)] // For the LSP
+pub struct Edit<'a> {
+    pub start: usize,
+    pub end: usize,
+    pub text: &'a str,
+}
+
+#[allow(dead_code)] // For the LSP
+impl Edit<'_> {
+    pub fn apply(&self, text: &str) -> String {
+        [&text[..self.start], self.text, &text[self.end..]].concat()
+    }
+
+    /// Moves a span that is after the edit to where it is in the edited text
+    fn shift(&self, span: Span) -> Span {
+        let start = span.start - self.end + self.start + self.text.len();
+        Span::new(span.file, start, start + span.end - span.start)
+    }
+}
+
+/// Lexes a whole file at once, use a [`Lexer`] to get the tokens as they are needed
+#[allow(dead_code)] // For the formatter and the LSP
+pub fn parse(file: &SourceFile, options: Options, interner: &Interner) -> Lexed {
+    event!(tracing::Level::DEBUG, "Starting parsing file {}: {}", file.name, file.text);
+    let mut lexer = Lexer::new(&file.text, file.id, options, interner);
+    let tokens = lexer.by_ref().collect();
+    let (errors, trivia) = lexer.finish();
+    Lexed { tokens, trivia, errors }
+}
+
+/// Lexes a file again after an edit, reusing as much as possible from the previous result.
+/// `file` must already contain the edit and `previous` must come from lexing the file before it.
+///
+/// Lexing restarts at the last token before the edit, and stops as soon as it produces a token
+/// that is also in the previous result at the same place (after the edit) in the same state,
+/// from there on the rest of the previous tokens are reused.
+#[allow(dead_code)] // For the LSP
+pub fn relex(file: &SourceFile, options: Options, interner: &Interner, previous: Lexed, edit: Edit) -> Lexed {
+    event!(tracing::Level::DEBUG, "Lexing file {} again after {:?}", file.name, edit);
+    let Lexed { tokens: old, trivia: old_trivia, errors: old_errors } = previous;
+
+    // How many interpolations each of the previous tokens is inside of, we can only restart and stop outside of them
+    let mut depth = 0;
+    let depths: Vec<usize> = old
+        .iter()
+        .map(|token| {
+            let before = depth;
+            match token.kind {
+                Kind::TextStart(_) => depth += 1,
+                Kind::TextEnd(_) => depth -= 1,
+                _ => {}
+            }
+            before
+        })
+        .collect();
+    // A token that ends right where the edit starts could grow, so it has to be lexed again too
+    let restart = (0..old.len())
+        .take_while(|&i| old[i].span.end < edit.start)
+        .filter(|&i| depths[i] == 0)
+        .last()
+        .unwrap_or(0);
+
+    // The tokens before the restart keep their trivia, so we start again at the leading trivia of the restart token
+    let (restart_pos, kept_trivia) = match old.get(restart) {
+        Some(token) if restart > 0 => match old_trivia.get(token.leading.0) {
+            Some(trivia) if token.leading.0 < token.leading.1 => (trivia.span.start, token.leading.0),
+            _ => (token.span.start, token.leading.0),
+        },
+        _ => (0, 0),
+    };
+    let mut tokens = old[..restart].to_vec();
+    let mut errors: Vec<Error> = old_errors.iter().filter(|error| error.span.start < restart_pos).copied().collect();
+    let mut trivia = old_trivia[..kept_trivia].to_vec();
+
+    let mut lexer = Lexer::new(&file.text, file.id, options, interner);
+    lexer.pos = restart_pos;
+    lexer.newline = restart > 0 && old[restart].newline_before;
+    lexer.previous = tokens.last().map(|token| token.kind);
+
+    let edit_end = edit.start + edit.text.len();
+    let mut candidate = old.partition_point(|token| token.span.start < edit.end);
+    let mut depth = 0;
+    let mut synchronized = None;
+    for token in lexer.by_ref() {
+        let token = Token {
+            leading: (token.leading.0 + kept_trivia, token.leading.1 + kept_trivia),
+            trailing: (token.trailing.0 + kept_trivia, token.trailing.1 + kept_trivia),
+            ..token
+        };
+        tokens.push(token);
+        match token.kind {
+            Kind::TextStart(_) => depth += 1,
+            Kind::TextEnd(_) => depth -= 1,
+            _ => {}
+        }
+        if token.span.start < edit_end || depth != 0 {
+            continue;
+        }
+        while candidate < old.len() && edit.shift(old[candidate].span).start < token.span.start {
+            candidate += 1;
+        }
+        let Some(&old_token) = old.get(candidate) else { continue };
+        if depths[candidate] != 0 || old_token.kind != token.kind || edit.shift(old_token.span) != token.span {
+            continue;
+        }
+        event!(tracing::Level::DEBUG, "Synchronized with the previous tokens at {:?}", token);
+        synchronized = Some((token, candidate));
+        break;
+    }
+    let (new_errors, new_trivia) = lexer.finish();
+
+    let Some((token, candidate)) = synchronized else {
+        errors.extend(new_errors);
+        trivia.extend(new_trivia);
+        return Lexed { tokens, trivia, errors };
+    };
+    // The lexer may have gone past the token to find its trailing trivia, what it found there is in the previous result too
+    let old_token = old[candidate];
+    let resume = old_token.span.end;
+    errors.extend(new_errors.into_iter().filter(|error| error.span.start < token.span.end));
+    trivia.extend_from_slice(&new_trivia[..token.trailing.1 - kept_trivia]);
+    let moved = |(start, end): (usize, usize)| {
+        (start - old_token.trailing.1 + token.trailing.1, end - old_token.trailing.1 + token.trailing.1)
+    };
+    tokens.extend(old[candidate + 1..].iter().map(|&token| Token {
+        span: edit.shift(token.span),
+        leading: moved(token.leading),
+        trailing: moved(token.trailing),
+        ..token
+    }));
+    errors.extend(old_errors.iter().filter(|error| error.span.start >= resume).map(|&error| Error {
+        span: edit.shift(error.span),
+        suggestion: error.suggestion.map(|suggestion| Suggestion {
+            span: edit.shift(suggestion.span),
+            ..suggestion
+        }),
+        ..error
+    }));
+    trivia.extend(old_trivia[old_token.trailing.1..].iter().map(|&trivia| Trivia {
+        span: edit.shift(trivia.span),
+        ..trivia
+    }));
+    Lexed { tokens, trivia, errors }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::SourceMap;
+    use std::path::Path;
+
+    fn collect_sources(dir: &Path, sources: &mut Vec<String>) {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                collect_sources(&path, sources);
+            } else if path.extension().is_some_and(|extension| extension == "🌸") {
+                sources.push(std::fs::read_to_string(path).unwrap());
+            }
+        }
+    }
+
+    fn lex(text: &str, options: Options, interner: &Interner) -> Lexed {
+        let mut sources = SourceMap::default();
+        let id = sources.add("test", text);
+        parse(sources.get(id), options, interner)
+    }
+
+    fn assert_same(relexed: &Lexed, fresh: &Lexed, before: &str, edit: Edit) {
+        let message = format!("edit {:?} on {:?}", edit, before);
+        assert_eq!(relexed.tokens, fresh.tokens, "{}", message);
+        assert_eq!(relexed.trivia, fresh.trivia, "{}", message);
+        assert_eq!(relexed.errors, fresh.errors, "{}", message);
+    }
+
+    /// Applies random edits to every test file and checks that relexing gives the same result
+    /// as lexing the edited text from scratch
+    #[test]
+    fn relex_matches_parse() {
+        const EDITS_PER_FILE: usize = 200;
+        let pieces = [
+            "", " ", "\t", "\n", "\"", "{", "}", "(", ")", ",", "<", ".", ";", ";;", ";; ", ";;)",
+            " ;;x", "\\", "r#\"", "\"#", "x", "12", "a.b", "if", "effect", "🌸",
+        ];
+        // xorshift, so failures can be reproduced
+        let mut seed: u64 = 0x5eed;
+        let mut random = move |n: usize| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % n.max(1) as u64) as usize
+        };
+
+        let mut files = Vec::new();
+        collect_sources(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests"), &mut files);
+        assert!(!files.is_empty());
+
+        let interner = Interner::default();
+        for trivia in [false, true] {
+            let options = Options { trivia, ..Default::default() };
+            for text in &files {
+                let mut sources = SourceMap::default();
+                let id = sources.add("test", text.clone());
+                let mut lexed = parse(sources.get(id), options, &interner);
+                if trivia {
+                    assert_eq!(lexed.reconstruct(text), *text);
+                }
+                let mut current = text.clone();
+                for _ in 0..EDITS_PER_FILE {
+                    let mut start = random(current.len() + 1);
+                    while !current.is_char_boundary(start) {
+                        start -= 1;
+                    }
+                    let mut end = (start + random(6)).min(current.len());
+                    while !current.is_char_boundary(end) {
+                        end -= 1;
+                    }
+                    let edit = Edit { start, end, text: pieces[random(pieces.len())] };
+                    let next = edit.apply(&current);
+                    // Each version gets its own map so the spans of both results point to the same file
+                    let mut sources = SourceMap::default();
+                    let id = sources.add("test", next.clone());
+                    let relexed = relex(sources.get(id), options, &interner, lexed, edit);
+                    let fresh = parse(sources.get(id), options, &interner);
+                    assert_same(&relexed, &fresh, &current, edit);
+                    if trivia {
+                        assert_eq!(relexed.reconstruct(&next), next);
+                    }
+                    lexed = relexed;
+                    current = next;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn doc_comments_are_block_comments() {
+        let text = ";;; block\nstill comment ;;\nx := 1";
+        let lexed = lex(text, Options { trivia: true, ..Default::default() }, &Interner::default());
+        assert!(lexed.errors.is_empty());
+        assert_eq!(lexed.trivia[0].kind, TriviaKind::DocComment);
+        assert_eq!((lexed.trivia[0].span.start, lexed.trivia[0].span.end), (0, 26));
+        assert_eq!(lexed.tokens.len(), 3);
+    }
+
+    #[test]
+    fn effect_is_a_keyword_before_a_signature() {
+        let text = "effect (Int) -> Int\nraise effect\nx := effect";
+        let interner = Interner::default();
+        let effect = Kind::Identifier(interner.intern("effect"));
+        for trivia in [false, true] {
+            let options = Options { edition: Edition::V7, trivia };
+            let kinds: Vec<_> = lex(text, options, &interner).tokens.iter().map(|token| token.kind).collect();
+            assert_eq!(kinds[0], Kind::Effect);
+            assert_eq!(kinds[7], effect);
+            assert_eq!(kinds[10], effect);
+        }
+    }
+
+    #[test]
+    fn unicode_in_comments_text_and_identifiers() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/Unsorted/002_unicode.🌸");
+        let text = std::fs::read_to_string(path).unwrap();
+        let interner = Interner::default();
+        let lexed = lex(&text, Options { trivia: true, ..Default::default() }, &interner);
+        let strings = interner.read();
+
+        let texts: Vec<_> = lexed
+            .tokens
+            .iter()
+            .filter_map(|token| match token.kind {
+                Kind::Text(symbol) => Some(strings.resolve(symbol).unwrap()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(texts, ["¡Hola, señor! 👋", "🌸🌼🌻"]);
+
+        let identifiers: Vec<_> = lexed
+            .tokens
+            .iter()
+            .filter_map(|token| match token.kind {
+                Kind::Identifier(symbol) => {
+                    let name = strings.resolve(symbol).unwrap();
+                    assert_eq!(&text[token.span.start..token.span.end], name);
+                    Some(name)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(identifiers, ["greeting", "flowers", "año", "π", "_private", "число"]);
+
+        let comments: Vec<_> = lexed
+            .trivia
+            .iter()
+            .filter(|trivia| matches!(trivia.kind, TriviaKind::LineComment | TriviaKind::BlockComment))
+            .map(|trivia| &text[trivia.span.start..trivia.span.end])
+            .collect();
+        assert!(comments[0].ends_with("unicode character 🌸"));
+        assert_eq!(comments[1], ";; Even block comments 🌺 with accents: canción, naïve, 日本語 ;;");
+
+        let flower = text.find("🌸 :=").unwrap();
+        assert_eq!(lexed.errors.len(), 1);
+        assert_eq!(lexed.errors[0].kind, ErrorKind::InvalidCharacter('🌸'));
+        assert_eq!((lexed.errors[0].span.start, lexed.errors[0].span.end), (flower, flower + '🌸'.len_utf8()));
+    }
 }